@@ -0,0 +1,127 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+/// The flavour of Base64 text a payload was pasted in, so output can be written the same way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Base64Variant {
+    /// Uses the URL-safe alphabet (`-` and `_` instead of `+` and `/`)
+    pub url_safe: bool,
+    /// Ends with `=` padding to a multiple of four characters
+    pub padded: bool,
+    /// Line length when the text is wrapped over several lines (e.g. 76 for MIME)
+    pub line_width: Option<usize>,
+}
+
+impl Default for Base64Variant {
+    fn default() -> Self {
+        Self {
+            url_safe: false,
+            padded: true,
+            line_width: None,
+        }
+    }
+}
+
+impl Base64Variant {
+    /// Decode Base64 text in any supported variant, detecting which one it is.
+    /// Whitespace, including line breaks, is ignored.
+    pub fn decode(text: &str) -> Result<(Vec<u8>, Self), base64::DecodeError> {
+        let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let variant = Self {
+            url_safe: compact.contains(['-', '_']),
+            padded: compact.ends_with('=') || compact.len().is_multiple_of(4),
+            line_width: (lines.len() > 1).then(|| lines[0].chars().count()),
+        };
+        let bytes = variant.engine().decode(&compact)?;
+        Ok((bytes, variant))
+    }
+
+    /// Encode bytes as Base64 text in this variant
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        let text = self.engine().encode(bytes);
+        match self.line_width {
+            Some(width) if width > 0 => text
+                .as_bytes()
+                .chunks(width)
+                // Base64 output is ASCII, so every chunk is valid UTF-8
+                .map(|line| String::from_utf8_lossy(line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => text,
+        }
+    }
+
+    /// Short description such as "base64url, unpadded" or "standard, wrapped at 76"
+    pub fn label(&self) -> String {
+        let alphabet = if self.url_safe {
+            "base64url"
+        } else {
+            "standard"
+        };
+        let mut parts = vec![alphabet.to_string()];
+        if !self.padded {
+            parts.push("unpadded".to_string());
+        }
+        if let Some(width) = self.line_width {
+            parts.push(format!("wrapped at {}", width));
+        }
+        parts.join(", ")
+    }
+
+    fn engine(&self) -> GeneralPurpose {
+        let alphabet = if self.url_safe {
+            &alphabet::URL_SAFE
+        } else {
+            &alphabet::STANDARD
+        };
+        let config = GeneralPurposeConfig::new()
+            .with_encode_padding(self.padded)
+            .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+        GeneralPurpose::new(alphabet, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_standard() {
+        let (bytes, variant) = Base64Variant::decode("gaVoZWxsb3s=").unwrap();
+        assert_eq!(bytes, vec![0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b]);
+        assert_eq!(variant, Base64Variant::default());
+        assert_eq!(variant.label(), "standard");
+    }
+
+    #[test]
+    fn test_decode_url_safe_unpadded() {
+        let (bytes, variant) = Base64Variant::decode("-_8").unwrap();
+        assert_eq!(bytes, vec![0xfb, 0xff]);
+        assert!(variant.url_safe);
+        assert!(!variant.padded);
+        assert_eq!(variant.label(), "base64url, unpadded");
+        assert_eq!(variant.encode(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_decode_wrapped() {
+        let text = "AAECAwQF\r\nBgcICQoL\r\nDA==\r\n";
+        let (bytes, variant) = Base64Variant::decode(text).unwrap();
+        assert_eq!(bytes, (0u8..13).collect::<Vec<_>>());
+        assert_eq!(variant.line_width, Some(8));
+        assert_eq!(variant.label(), "standard, wrapped at 8");
+        assert_eq!(variant.encode(&bytes), "AAECAwQF\nBgcICQoL\nDA==");
+    }
+
+    #[test]
+    fn test_decode_rejects_mixed_alphabets() {
+        assert!(Base64Variant::decode("+-8=").is_err());
+        assert!(Base64Variant::decode("not base64!").is_err());
+    }
+}
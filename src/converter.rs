@@ -1,87 +1,751 @@
-use base64::Engine;
+use rmp::Marker;
 use serde_json::Value;
 
+use crate::base64_variant::Base64Variant;
+use crate::error::{key_path_segment, ConvertError};
+use crate::format;
+use crate::hex_input::{parse_hex_input, HexFormat};
+use crate::literal::{render_literal, LiteralLanguage};
+use crate::position_mapper::{
+    disassemble, format_hex_dump, parse_msgpack_value_with_report, ByteRange, DecodeOptions,
+    DecodeReport, HexLayout, Limits, StreamFormat, WideHeader,
+};
+use crate::tagged;
+
+/// Result of a msgpack to JSON conversion
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedJson {
+    /// Pretty-printed JSON
+    pub json: String,
+    /// Problems with the input that did not stop the conversion
+    pub warnings: Vec<String>,
+    /// Byte range `(start, end)` of data left over after the top-level value
+    pub trailing: Option<(usize, usize)>,
+    /// In partial mode, byte ranges `(start, end)` of the values the data ran out in,
+    /// from their header to the end of the data
+    pub truncated: Vec<ByteRange>,
+    /// In resync mode, byte ranges `(start, end)` of corrupt data that was skipped
+    pub skipped: Vec<ByteRange>,
+    /// Byte ranges `(start, end)` of map keys that repeat an earlier key of their map
+    pub duplicate_keys: Vec<ByteRange>,
+    /// In lint mode, headers wider than their value needs, in byte order
+    pub wide_headers: Vec<WideHeader>,
+}
+
 /// Convert Base64-encoded msgpack data to pretty JSON string
 /// Supports uint64 values by using serde_json's arbitrary precision feature
-pub fn msgpack_to_json(base64_string: &str) -> Result<String, String> {
+pub fn msgpack_to_json(
+    base64_string: &str,
+    options: &DecodeOptions,
+) -> Result<DecodedJson, ConvertError> {
     // Decode base64 to binary
-    let bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64_string)
-        .map_err(|e| format!("Failed to decode base64: {}", e))?;
+    let (bytes, _) = Base64Variant::decode(base64_string)
+        .map_err(|e| ConvertError::InvalidBase64(e.to_string()))?;
 
     // Decode msgpack to serde_json::Value
-    let value: Value =
-        rmp_serde::from_slice(&bytes).map_err(|e| format!("Failed to decode msgpack: {}", e))?;
+    let (value, end, report) = if options.stream == StreamFormat::Off {
+        parse_msgpack_value_with_report(&bytes, 0, options)?
+    } else {
+        let (values, report) = decode_stream(&bytes, options)?;
+        (Value::Array(values), bytes.len(), report)
+    };
+    let skipped = merge_ranges(report.skipped);
+    let mut wide_headers = report.wide_headers;
+    wide_headers.sort_by_key(|header| header.range);
+    let trailing = (end < bytes.len()).then_some((end, bytes.len()));
+
+    let mut warnings: Vec<String> = tagged::find_tagged(&value, tagged::RAW_STR_TAG)
+        .into_iter()
+        .map(|(path, _)| {
+            format!(
+                "String at {} is not valid UTF-8; its raw bytes are kept as {{\"{}\": \"<base64>\"}}",
+                path,
+                tagged::RAW_STR_TAG
+            )
+        })
+        .collect();
+    for (path, marker) in tagged::find_tagged(&value, tagged::TRUNCATED_TAG) {
+        let offset = marker["offset"].as_u64().unwrap_or_default() as usize;
+        warnings.push(format!(
+            "Data ends at offset {} inside the value at {}; everything before it is decoded and the rest is marked {{\"{}\": ...}}",
+            offset,
+            path,
+            tagged::TRUNCATED_TAG
+        ));
+    }
+    for &(start, end) in &skipped {
+        warnings.push(format!(
+            "Skipped {} corrupt byte{} at offset {} (0x{:x}) and resumed at offset {}",
+            end - start,
+            if end - start == 1 { "" } else { "s" },
+            start,
+            start,
+            end
+        ));
+    }
+    for duplicate in &report.duplicate_keys {
+        let (start, _) = duplicate.range;
+        warnings.push(format!(
+            "Map key {} at offset {} (0x{:x}) repeats an earlier key; {}",
+            duplicate.key,
+            start,
+            start,
+            if duplicate.kept {
+                format!(
+                    "all entries are kept as {{\"{}\": [[key, value], ...]}} pairs",
+                    tagged::MAP_TAG
+                )
+            } else {
+                "only the last value is shown".to_string()
+            }
+        ));
+    }
+    if let Some((start, end)) = trailing {
+        warnings.push(format!(
+            "{} trailing byte{} after the top-level value at offset {} (0x{:x})",
+            end - start,
+            if end - start == 1 { "" } else { "s" },
+            start,
+            start
+        ));
+    }
+
+    // Convert to pretty JSON, or one compact document per line for NDJSON
+    let json = match (&value, options.stream) {
+        (Value::Array(values), StreamFormat::Ndjson) => values
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map(|lines| lines.join("\n")),
+        _ => serde_json::to_string_pretty(&value),
+    }
+    .map_err(|e| ConvertError::Serialize(e.to_string()))?;
+    Ok(DecodedJson {
+        json,
+        warnings,
+        trailing,
+        truncated: report.truncated,
+        skipped,
+        duplicate_keys: report
+            .duplicate_keys
+            .into_iter()
+            .map(|duplicate| duplicate.range)
+            .collect(),
+        wide_headers,
+    })
+}
+
+/// Decode every concatenated top-level value in the buffer. Errors are located
+/// as if the stream were the JSON array it is shown as.
+fn decode_stream(
+    bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<(Vec<Value>, DecodeReport), ConvertError> {
+    let mut values = Vec::new();
+    let mut report = DecodeReport::default();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let (value, end, value_report) = parse_msgpack_value_with_report(bytes, pos, options)
+            .map_err(|e| e.within_index(values.len()))?;
+        values.push(value);
+        report.skipped.extend(value_report.skipped);
+        report.duplicate_keys.extend(value_report.duplicate_keys);
+        report.wide_headers.extend(value_report.wide_headers);
+        report.truncated.extend(value_report.truncated);
+        pos = end;
+    }
+    Ok((values, report))
+}
 
-    // Convert to pretty JSON
-    serde_json::to_string_pretty(&value).map_err(|e| format!("Failed to serialize JSON: {}", e))
+/// Sort byte ranges and join the ones that touch or overlap
+fn merge_ranges(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_unstable();
+    let mut merged: Vec<ByteRange> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// How JSON integers outside the 64-bit range (above `u64::MAX` or below `i64::MIN`)
+/// are encoded. msgpack has no integer format for them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BigIntEncoding {
+    /// Refuse to encode them
+    #[default]
+    Error,
+    /// Encode them as a str of their decimal digits
+    String,
+    /// Encode them as an ext value of the given type holding the minimal big-endian
+    /// two's complement bytes of the integer
+    Ext(i8),
+}
+
+impl BigIntEncoding {
+    /// Extension type used for big integers when none is chosen
+    pub const DEFAULT_EXT_TYPE: i8 = 1;
+}
+
+/// Options controlling how JSON is encoded as msgpack.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EncodeOptions {
+    /// Encode untagged floats as float32 instead of float64
+    pub float32: bool,
+    /// Encode each element of a top-level array (`JsonArray`) or each JSON document
+    /// (`Ndjson`) as its own value, concatenated into one buffer
+    pub stream: StreamFormat,
+    /// Base64 flavour of the output, normally the one the input was pasted in
+    pub base64: Base64Variant,
+    /// Bounds on the size of the JSON text and of the encoded msgpack
+    pub limits: Limits,
+    /// Representation of integers that do not fit in 64 bits
+    pub big_int: BigIntEncoding,
+    /// Produce the same bytes for equal documents: map entries sorted by encoded key,
+    /// wire-format annotations ignored in favour of the smallest format, integral floats
+    /// such as `1e2` stored as the integer `100`, and other floats as float32 whenever
+    /// that is exact, with a single NaN
+    pub canonical: bool,
+    /// Encode untagged floats as float32 when that loses nothing
+    pub lossless_float32: bool,
+    /// Encode floats with an integral value, such as `2.0`, as integers
+    pub integral_floats_as_ints: bool,
+    /// Ignore wire-format annotations such as `{"$uint16": 1}`, giving every value its
+    /// smallest header
+    pub narrowest_headers: bool,
+}
+
+impl EncodeOptions {
+    /// Whether any option that trades fidelity to the input for a smaller encoding is set
+    pub fn minimizes(&self) -> bool {
+        self.lossless_float32 || self.integral_floats_as_ints || self.narrowest_headers
+    }
 }
 
 /// Convert JSON string to Base64-encoded msgpack data
-pub fn json_to_msgpack(json_string: &str) -> Result<String, String> {
+pub fn json_to_msgpack(json_string: &str, options: &EncodeOptions) -> Result<String, ConvertError> {
+    let bytes = encode_json(json_string, options)?;
+    Ok(options.base64.encode(&bytes))
+}
+
+/// Byte counts of the msgpack for `json_string` without and with the size-minimising
+/// options, to show what they save
+pub fn minimized_sizes(
+    json_string: &str,
+    options: &EncodeOptions,
+) -> Result<(usize, usize), ConvertError> {
+    let plain = EncodeOptions {
+        lossless_float32: false,
+        integral_floats_as_ints: false,
+        narrowest_headers: false,
+        ..options.clone()
+    };
+    Ok((
+        encode_json(json_string, &plain)?.len(),
+        encode_json(json_string, options)?.len(),
+    ))
+}
+
+/// Parse JSON text and encode it as msgpack bytes
+fn encode_json(json_string: &str, options: &EncodeOptions) -> Result<Vec<u8>, ConvertError> {
+    let max_size = options.limits.max_size;
+    if json_string.len() > max_size {
+        return Err(ConvertError::Encode(format!(
+            "JSON text of {} bytes exceeds the size limit of {} bytes",
+            json_string.len(),
+            max_size
+        )));
+    }
+
     // Parse JSON to serde_json::Value
-    let value: Value =
-        serde_json::from_str(json_string).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let documents: Vec<Value> = if options.stream == StreamFormat::Ndjson {
+        serde_json::Deserializer::from_str(json_string)
+            .into_iter()
+            .collect::<Result<_, _>>()
+    } else {
+        serde_json::from_str(json_string).map(|value| vec![value])
+    }
+    .map_err(|e| ConvertError::InvalidJson {
+        message: e.to_string(),
+        range: json_error_range(json_string, e.line(), e.column()),
+    })?;
+    let values = match (options.stream, documents.as_slice()) {
+        (StreamFormat::JsonArray, [Value::Array(items)]) => items.as_slice(),
+        (StreamFormat::JsonArray, _) => {
+            return Err(ConvertError::Encode(
+                "stream mode expects a JSON array of values".to_string(),
+            ))
+        }
+        _ => documents.as_slice(),
+    };
+
+    let find = |matches: fn(&serde_json::Number) -> bool| {
+        values
+            .iter()
+            .enumerate()
+            .find_map(|(i, value)| find_number(value, stream_path(options.stream, i), matches))
+    };
+    if options.big_int == BigIntEncoding::Error {
+        if let Some((path, literal)) = find(is_big_integer) {
+            return Err(ConvertError::Encode(format!(
+                "integer {} at {} is outside the 64-bit range; choose a big-integer encoding (string or ext) to keep it",
+                literal, path
+            )));
+        }
+    }
+    if let Some((path, literal)) = find(is_out_of_range_float) {
+        return Err(ConvertError::Encode(format!(
+            "number {} at {} is outside the float64 range",
+            literal, path
+        )));
+    }
 
     // Encode to msgpack
-    let bytes =
-        rmp_serde::to_vec(&value).map_err(|e| format!("Failed to encode msgpack: {}", e))?;
+    let mut bytes = Vec::new();
+    for value in values {
+        encode_value(value, &mut bytes, options).map_err(ConvertError::Encode)?;
+    }
+    if bytes.len() > max_size {
+        return Err(ConvertError::Encode(format!(
+            "encoded msgpack of {} bytes exceeds the size limit of {} bytes",
+            bytes.len(),
+            max_size
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Byte range of the character a JSON parse error points at, given the error's
+/// 1-based line and byte column. Errors at the end of the input point at the
+/// last non-whitespace character, where the document was cut off.
+fn json_error_range(json: &str, line: usize, column: usize) -> (usize, usize) {
+    let line_start: usize = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let mut start = (line_start + column.saturating_sub(1)).min(json.len());
+    while !json.is_char_boundary(start) {
+        start -= 1;
+    }
+    let content_end = json.trim_end().len();
+    if start >= content_end {
+        start = json[..content_end]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i);
+    }
+    let end = json[start..]
+        .chars()
+        .next()
+        .map_or(start, |c| start + c.len_utf8());
+    (start, end)
+}
 
-    // Convert to base64
-    Ok(base64::engine::general_purpose::STANDARD.encode(&bytes))
+/// JSON path of the `index`th value being encoded: the root, or an element of the stream
+fn stream_path(stream: StreamFormat, index: usize) -> String {
+    match stream {
+        StreamFormat::Off => "$".to_string(),
+        _ => format!("$[{}]", index),
+    }
 }
 
-/// Convert Base64 string to hex string with space-separated bytes
-pub fn base64_to_hex(base64_string: &str) -> Result<String, String> {
-    let bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64_string)
-        .map_err(|_| "Invalid Base64 string".to_string())?;
+/// Whether a JSON number is an integer literal that fits neither `u64` nor `i64`
+fn is_big_integer(n: &serde_json::Number) -> bool {
+    let digits = n.as_str().trim_start_matches('-');
+    n.as_u64().is_none() && n.as_i64().is_none() && digits.bytes().all(|b| b.is_ascii_digit())
+}
 
-    Ok(bytes
-        .iter()
-        .map(|b| format!("{:02X}", b))
-        .collect::<Vec<_>>()
-        .join(" "))
+/// Whether a JSON number is a non-integer literal too large for a float64, e.g. `1e400`
+fn is_out_of_range_float(n: &serde_json::Number) -> bool {
+    !is_big_integer(n) && n.as_f64().is_none()
+}
+
+/// JSON path and text of the first number in `value` that `matches`
+fn find_number(
+    value: &Value,
+    path: String,
+    matches: fn(&serde_json::Number) -> bool,
+) -> Option<(String, String)> {
+    match value {
+        Value::Number(n) if matches(n) => Some((path, n.to_string())),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(i, item)| find_number(item, format!("{}[{}]", path, i), matches)),
+        Value::Object(obj) => obj.iter().find_map(|(key, item)| {
+            find_number(item, format!("{}{}", path, key_path_segment(key)), matches)
+        }),
+        _ => None,
+    }
+}
+
+/// Minimal big-endian two's complement bytes of a decimal integer literal
+fn big_int_to_bytes(literal: &str) -> Vec<u8> {
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, literal),
+    };
+    // Magnitude in little-endian base 256
+    let mut bytes: Vec<u8> = Vec::new();
+    for digit in digits.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in bytes.iter_mut() {
+            let product = *byte as u32 * 10 + carry;
+            *byte = product as u8;
+            carry = product >> 8;
+        }
+        if carry > 0 {
+            bytes.push(carry as u8);
+        }
+    }
+    let sign_bit_set = |bytes: &[u8]| bytes.last().is_some_and(|b| b & 0x80 != 0);
+    if negative {
+        // Invert and add one
+        let mut carry = 1u16;
+        for byte in bytes.iter_mut() {
+            let sum = !*byte as u16 + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        if !sign_bit_set(&bytes) {
+            bytes.push(0xff);
+        }
+    } else if sign_bit_set(&bytes) {
+        bytes.push(0x00);
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn encode_error(e: impl std::fmt::Display) -> String {
+    e.to_string()
+}
+
+/// Encode a JSON value as msgpack, choosing the smallest header for every value.
+/// Tagged objects (see `tagged`) are encoded as the msgpack type they represent,
+/// and wire-format annotations such as `{"$uint16": 1}` force a specific format.
+fn encode_value(value: &Value, buf: &mut Vec<u8>, options: &EncodeOptions) -> Result<(), String> {
+    if let Some((name, payload)) = tagged::as_tagged(value) {
+        if let Some(marker) = tagged::annotation_format(name) {
+            if !(options.canonical || options.narrowest_headers) {
+                return encode_with_format(marker, payload, buf, options);
+            }
+            // Keep only the annotated value
+            if format::family(marker) == format::Family::Float {
+                let bits = annotated_float_bits(marker, payload)?;
+                let f = match marker {
+                    Marker::F32 => f32::from_bits(bits as u32) as f64,
+                    _ => f64::from_bits(bits),
+                };
+                return encode_float(f, buf, options);
+            }
+            return encode_value(payload, buf, options);
+        }
+    }
+    match value {
+        Value::Null => rmp::encode::write_nil(buf).map_err(encode_error),
+        Value::Bool(b) => rmp::encode::write_bool(buf, *b).map_err(encode_error),
+        Value::Number(n) if is_big_integer(n) => match options.big_int {
+            BigIntEncoding::Error => Err(format!("integer {} is outside the 64-bit range", n)),
+            BigIntEncoding::String => rmp::encode::write_str(buf, n.as_str()).map_err(encode_error),
+            BigIntEncoding::Ext(ext_type) => {
+                encode_ext(ext_type, &big_int_to_bytes(n.as_str()), buf)
+            }
+        },
+        Value::Number(n) => {
+            // `-0` stays a float, as JSON parsers read it as negative zero
+            if let Some(u) = n.as_u64() {
+                rmp::encode::write_uint(buf, u).map_err(encode_error)?;
+            } else if let Some(i) = n.as_i64().filter(|_| n.as_str() != "-0") {
+                rmp::encode::write_sint(buf, i).map_err(encode_error)?;
+            } else {
+                let f = n
+                    .as_f64()
+                    .ok_or_else(|| format!("number {} is outside the float64 range", n))?;
+                encode_float(f, buf, options)?;
+            }
+            Ok(())
+        }
+        Value::String(s) => rmp::encode::write_str(buf, s).map_err(encode_error),
+        Value::Array(arr) => {
+            rmp::encode::write_array_len(buf, encode_len(arr.len())?).map_err(encode_error)?;
+            arr.iter()
+                .try_for_each(|item| encode_value(item, buf, options))
+        }
+        Value::Object(obj) => match tagged::as_tagged(value) {
+            Some((tagged::BIN_TAG, payload)) => {
+                let data = tagged::json_to_bin(payload)?;
+                rmp::encode::write_bin(buf, &data).map_err(encode_error)
+            }
+            Some((tagged::RAW_STR_TAG, payload)) => {
+                let data = tagged::json_to_raw_str(payload)?;
+                rmp::encode::write_str_len(buf, encode_len(data.len())?).map_err(encode_error)?;
+                buf.extend_from_slice(&data);
+                Ok(())
+            }
+            Some((tagged::MAP_TAG, payload)) => {
+                let pairs = tagged::json_to_map_pairs(payload)?;
+                let mut entries = Vec::with_capacity(pairs.len());
+                for (key, item) in pairs {
+                    let mut entry = Vec::new();
+                    encode_value(key, &mut entry, options)?;
+                    encode_value(item, &mut entry, options)?;
+                    entries.push(entry);
+                }
+                write_map(entries, buf, options)
+            }
+            Some((tagged::EXT_TAG, payload)) => {
+                let (ext_type, data) = tagged::json_to_ext(payload)?;
+                encode_ext(ext_type, &data, buf)
+            }
+            Some((tagged::TRUNCATED_TAG, _)) => Err(format!(
+                "{} marks data missing from a truncated payload and cannot be encoded",
+                tagged::TRUNCATED_TAG
+            )),
+            Some((tagged::TIMESTAMP_TAG, payload)) => {
+                let data = tagged::json_to_timestamp(payload)?;
+                encode_ext(tagged::TIMESTAMP_EXT_TYPE, &data, buf)
+            }
+            _ => {
+                let mut entries = Vec::with_capacity(obj.len());
+                for (key, item) in obj {
+                    let mut entry = Vec::new();
+                    rmp::encode::write_str(&mut entry, key).map_err(encode_error)?;
+                    encode_value(item, &mut entry, options)?;
+                    entries.push(entry);
+                }
+                write_map(entries, buf, options)
+            }
+        },
+    }
+}
+
+/// Encode a value using exactly the format requested by a wire-format annotation
+fn encode_with_format(
+    marker: Marker,
+    payload: &Value,
+    buf: &mut Vec<u8>,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let mismatch = || annotation_mismatch(marker);
+    if format::family(marker) == format::Family::Float {
+        let bits = annotated_float_bits(marker, payload)?;
+        buf.push(marker.to_u8());
+        match marker {
+            Marker::F32 => buf.extend_from_slice(&(bits as u32).to_be_bytes()),
+            _ => buf.extend_from_slice(&bits.to_be_bytes()),
+        }
+        return Ok(());
+    }
+
+    // Encode the payload normally, then rewrite its header with the requested width
+    let mut inner = Vec::new();
+    encode_value(payload, &mut inner, options)?;
+    if format::family(Marker::from_u8(inner[0])) != format::family(marker) {
+        return Err(mismatch());
+    }
+    if format::family(marker) == format::Family::Int {
+        let value = format::read_int(&inner, 0)?;
+        return format::write_int(buf, marker, value).map_err(encode_error);
+    }
+    let (len, header_size) = format::read_length_header(&inner, 0)?.ok_or_else(mismatch)?;
+    let ext_type = inner[header_size - 1] as i8;
+    format::write_length_header(buf, marker, len, ext_type).map_err(encode_error)?;
+    buf.extend_from_slice(&inner[header_size..]);
+    Ok(())
 }
 
-/// Convert hex string (space-separated or continuous) to Base64 string
-pub fn hex_to_base64(hex_string: &str) -> Result<String, String> {
-    // Remove all whitespace
-    let clean_hex: String = hex_string.chars().filter(|c| !c.is_whitespace()).collect();
+fn annotation_mismatch(marker: Marker) -> String {
+    format!(
+        "value does not match ${} annotation",
+        format::format_name(marker)
+    )
+}
 
-    if clean_hex.is_empty() {
-        return Ok(String::new());
+/// Bit pattern of the value of a `$float32`/`$float64` annotation, at the annotated width.
+/// Non-finite floats are written as strings such as "NaN" or "-Infinity".
+fn annotated_float_bits(marker: Marker, payload: &Value) -> Result<u64, String> {
+    match payload {
+        Value::String(text) => tagged::parse_non_finite(marker, text).map_err(encode_error),
+        _ => {
+            let f = payload
+                .as_f64()
+                .ok_or_else(|| annotation_mismatch(marker))?;
+            Ok(match marker {
+                Marker::F32 => (f as f32).to_bits() as u64,
+                _ => f.to_bits(),
+            })
+        }
     }
+}
 
-    if !clean_hex.len().is_multiple_of(2) {
-        return Err("Hex string must have an even number of characters".to_string());
+/// Encode an untagged float as float64, or as float32 when asked to. Canonical mode and
+/// the minimising options write integral values as integers and pick float32 whenever it
+/// holds the value exactly, and canonical mode also writes every NaN the same way.
+fn encode_float(f: f64, buf: &mut Vec<u8>, options: &EncodeOptions) -> Result<(), String> {
+    // -0.0 has no integer form; 2^64 and -2^63 are exact as f64
+    let integral_as_int = options.canonical || options.integral_floats_as_ints;
+    if integral_as_int && f.fract() == 0.0 && !(f == 0.0 && f.is_sign_negative()) {
+        if (0.0..18_446_744_073_709_551_616.0).contains(&f) {
+            return rmp::encode::write_uint(buf, f as u64)
+                .map(|_| ())
+                .map_err(encode_error);
+        }
+        if (-9_223_372_036_854_775_808.0..0.0).contains(&f) {
+            return rmp::encode::write_sint(buf, f as i64)
+                .map(|_| ())
+                .map_err(encode_error);
+        }
+    }
+    let lossless_float32 = options.canonical || options.lossless_float32;
+    if options.float32 {
+        rmp::encode::write_f32(buf, f as f32).map_err(encode_error)?;
+    } else if options.canonical && f.is_nan() {
+        rmp::encode::write_f32(buf, f32::NAN).map_err(encode_error)?;
+    } else if lossless_float32 && (f as f32) as f64 == f {
+        rmp::encode::write_f32(buf, f as f32).map_err(encode_error)?;
+    } else {
+        rmp::encode::write_f64(buf, f).map_err(encode_error)?;
     }
+    Ok(())
+}
 
-    // Validate hex characters
-    if !clean_hex.chars().all(|c| c.is_ascii_hexdigit()) {
-        return Err("Invalid hex characters".to_string());
+/// Write a map from its encoded key-value entries. Canonical mode sorts the entries
+/// bytewise; no msgpack encoding is a prefix of another, so this orders them by their
+/// encoded keys: shorter strings first, then byte by byte.
+fn write_map(
+    mut entries: Vec<Vec<u8>>,
+    buf: &mut Vec<u8>,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    if options.canonical {
+        entries.sort_unstable();
+    }
+    rmp::encode::write_map_len(buf, encode_len(entries.len())?).map_err(encode_error)?;
+    for entry in entries {
+        buf.extend_from_slice(&entry);
     }
+    Ok(())
+}
+
+/// Encode an extension value with the smallest fixext/ext header
+fn encode_ext(ext_type: i8, data: &[u8], buf: &mut Vec<u8>) -> Result<(), String> {
+    rmp::encode::write_ext_meta(buf, encode_len(data.len())?, ext_type).map_err(encode_error)?;
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+/// Check that a length fits in a 32-bit msgpack length prefix
+fn encode_len(len: usize) -> Result<u32, String> {
+    u32::try_from(len).map_err(|_| format!("length {} exceeds u32::MAX", len))
+}
+
+/// Convert Base64 string to hex text in the given layout: space-separated bytes,
+/// or `hexdump -C` style rows with offsets and an ASCII column
+pub fn base64_to_hex(base64_string: &str, layout: HexLayout) -> Result<String, ConvertError> {
+    let (bytes, _) = Base64Variant::decode(base64_string)
+        .map_err(|e| ConvertError::InvalidBase64(e.to_string()))?;
+
+    Ok(match layout {
+        HexLayout::Inline => bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" "),
+        HexLayout::Dump => format_hex_dump(&bytes),
+    })
+}
+
+/// Convert Base64 string to a source-code literal of the bytes in the given language,
+/// wrapped to `line_width` characters (0 for a single line)
+pub fn base64_to_literal(
+    base64_string: &str,
+    language: LiteralLanguage,
+    line_width: usize,
+) -> Result<String, ConvertError> {
+    let (bytes, _) = Base64Variant::decode(base64_string)
+        .map_err(|e| ConvertError::InvalidBase64(e.to_string()))?;
+    Ok(render_literal(&bytes, language, line_width))
+}
+
+/// Longest decoded value shown on a disassembly line, in characters
+const DISASSEMBLY_VALUE_WIDTH: usize = 64;
 
-    // Convert hex to bytes - safe to unwrap since we validated hex characters above
-    let bytes: Vec<u8> = (0..clean_hex.len())
-        .step_by(2)
-        .map(|i| {
-            u8::from_str_radix(&clean_hex[i..i + 2], 16).map_err(|_| "Invalid hex byte".to_string())
+/// Convert Base64 string to a disassembly listing with one line per msgpack token:
+/// offset, header bytes, then the format name, declared length and decoded value,
+/// indented by nesting depth. A value that fails to decode ends the listing with its error.
+pub fn base64_to_disassembly(
+    base64_string: &str,
+    options: &DecodeOptions,
+) -> Result<String, ConvertError> {
+    let (bytes, _) = Base64Variant::decode(base64_string)
+        .map_err(|e| ConvertError::InvalidBase64(e.to_string()))?;
+    let (tokens, error) = disassemble(&bytes, options);
+
+    let mut lines: Vec<String> = tokens
+        .iter()
+        .map(|token| {
+            let (start, end) = token.header;
+            let header = bytes[start..end]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut line = format!(
+                "{:08x}  {:<26}  {}{}",
+                start,
+                header,
+                "  ".repeat(token.depth),
+                format::format_name(token.format)
+            );
+            if let Some(len) = token.length {
+                line.push_str(&format!("  len {}", len));
+            }
+            if let Some(value) = &token.value {
+                let text = value.to_string();
+                line.push_str("  ");
+                if text.chars().count() > DISASSEMBLY_VALUE_WIDTH {
+                    line.extend(text.chars().take(DISASSEMBLY_VALUE_WIDTH - 1));
+                    line.push('…');
+                } else {
+                    line.push_str(&text);
+                }
+            }
+            line
         })
-        .collect::<Result<Vec<u8>, String>>()?;
+        .collect();
+    if let Some(e) = error {
+        lines.push(format!("{:08x}  error: {}", e.offset, e));
+    }
+    Ok(lines.join("\n"))
+}
 
-    Ok(base64::engine::general_purpose::STANDARD.encode(&bytes))
+/// Convert hex text to a Base64 string in the given variant.
+/// Accepts plain hex as well as xxd, hexdump -C and Wireshark dumps, `0x` byte lists
+/// and `\x` escape strings, and reports which layout was detected.
+pub fn hex_to_base64(
+    hex_string: &str,
+    variant: &Base64Variant,
+) -> Result<(String, HexFormat), ConvertError> {
+    let (bytes, format) = parse_hex_input(hex_string).map_err(ConvertError::InvalidHex)?;
+    Ok((variant.encode(&bytes), format))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::Engine;
 
     #[test]
     fn test_msgpack_to_json_simple() {
         // {"hello": "world"} in msgpack, base64 encoded
         let msgpack_base64 = "gaVoZWxsb6V3b3JsZA==";
-        let json = msgpack_to_json(msgpack_base64).unwrap();
+        let json = msgpack_to_json(msgpack_base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
         let parsed: Value = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed["hello"], "world");
     }
@@ -89,38 +753,877 @@ mod tests {
     #[test]
     fn test_json_to_msgpack_roundtrip() {
         let json = r#"{"hello": "world"}"#;
-        let msgpack = json_to_msgpack(json).unwrap();
-        let back_to_json = msgpack_to_json(&msgpack).unwrap();
+        let msgpack = json_to_msgpack(json, &EncodeOptions::default()).unwrap();
+        let back_to_json = msgpack_to_json(&msgpack, &DecodeOptions::default())
+            .unwrap()
+            .json;
         let parsed: Value = serde_json::from_str(&back_to_json).unwrap();
         assert_eq!(parsed["hello"], "world");
     }
 
     #[test]
     fn test_base64_to_hex() {
-        let hex = base64_to_hex("aGVsbG8=").unwrap();
+        let hex = base64_to_hex("aGVsbG8=", HexLayout::Inline).unwrap();
         assert_eq!(hex, "68 65 6C 6C 6F");
     }
 
     #[test]
     fn test_hex_to_base64() {
-        let base64 = hex_to_base64("68 65 6C 6C 6F").unwrap();
+        let (base64, format) = hex_to_base64("68 65 6C 6C 6F", &Base64Variant::default()).unwrap();
         assert_eq!(base64, "aGVsbG8=");
+        assert_eq!(format, HexFormat::Plain);
+    }
+
+    #[test]
+    fn test_hex_to_base64_dump() {
+        let dump =
+            "00000000  68 65 6c 6c 6f                                    |hello|\n00000005\n";
+        let (base64, format) = hex_to_base64(dump, &Base64Variant::default()).unwrap();
+        assert_eq!(base64, "aGVsbG8=");
+        assert_eq!(format, HexFormat::HexdumpCanonical);
+    }
+
+    #[test]
+    fn test_base64_to_literal_roundtrip() {
+        let literal = base64_to_literal("gaVoZWxsbw==", LiteralLanguage::RustVec, 0).unwrap();
+        assert_eq!(literal, "vec![0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f]");
+        let (base64, format) = hex_to_base64(&literal, &Base64Variant::default()).unwrap();
+        assert_eq!(base64, "gaVoZWxsbw==");
+        assert_eq!(format, HexFormat::PrefixedBytes);
+        assert!(base64_to_literal("!!", LiteralLanguage::C, 0).is_err());
+    }
+
+    #[test]
+    fn test_base64_to_hex_dump_roundtrip() {
+        let original = "gaVoZWxsb6V3b3JsZA==";
+        let dump = base64_to_hex(original, HexLayout::Dump).unwrap();
+        assert_eq!(
+            dump,
+            "00000000  81 A5 68 65 6C 6C 6F A5  77 6F 72 6C 64           |..hello.world|"
+        );
+        let (result, format) = hex_to_base64(&dump, &Base64Variant::default()).unwrap();
+        assert_eq!(result, original);
+        assert_eq!(format, HexFormat::HexdumpCanonical);
     }
 
     #[test]
     fn test_roundtrip_base64_hex() {
         let original = "gaVoZWxsb6V3b3JsZA==";
-        let hex = base64_to_hex(original).unwrap();
-        let result = hex_to_base64(&hex).unwrap();
+        let hex = base64_to_hex(original, HexLayout::Inline).unwrap();
+        let (result, _) = hex_to_base64(&hex, &Base64Variant::default()).unwrap();
         assert_eq!(result, original);
     }
 
+    #[test]
+    fn test_ext_roundtrip_preserves_bytes() {
+        // [fixext1(type 1, 0x2a), ext8(type -5, 3 bytes)]
+        let bytes = vec![0x92, 0xd4, 0x01, 0x2a, 0xc7, 0x03, 0xfb, 0x01, 0x02, 0x03];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0]["$ext"]["type"], 1);
+        assert_eq!(parsed[0]["$ext"]["data"], "Kg==");
+        assert_eq!(parsed[1]["$ext"]["type"], -5);
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_ext_invalid_payload() {
+        assert!(json_to_msgpack(
+            r#"{"$ext": {"type": 1, "data": "!!"}}"#,
+            &EncodeOptions::default()
+        )
+        .is_err());
+        assert!(
+            json_to_msgpack(r#"{"$ext": {"data": "AA=="}}"#, &EncodeOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_timestamp_roundtrip() {
+        // [timestamp32, timestamp64, timestamp96]
+        let bytes = [
+            vec![0x93, 0xd6, 0xff, 0x65, 0x53, 0xf1, 0x00],
+            vec![0xd7, 0xff, 0x1d, 0x53, 0x53, 0x00, 0x65, 0x53, 0xf1, 0x00],
+            vec![0xc7, 0x0c, 0xff, 0x00, 0x00, 0x00, 0x00],
+            (-1i64).to_be_bytes().to_vec(),
+        ]
+        .concat();
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0]["$timestamp"], "2023-11-14T22:13:20Z");
+        assert_eq!(parsed[1]["$timestamp"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(parsed[2]["$timestamp"], "1969-12-31T23:59:59Z");
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_timestamp_encoding_normalises_offset() {
+        let msgpack = json_to_msgpack(
+            r#"{"$timestamp": "2023-11-15T00:13:20+02:00"}"#,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let json = msgpack_to_json(&msgpack, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["$timestamp"], "2023-11-14T22:13:20Z");
+        assert!(
+            json_to_msgpack(r#"{"$timestamp": "yesterday"}"#, &EncodeOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_bin_roundtrip_preserves_bytes() {
+        // {"b": bin8(00 ff), "s": "x"}
+        let bytes = vec![
+            0x82, 0xa1, 0x62, 0xc4, 0x02, 0x00, 0xff, 0xa1, 0x73, 0xa1, 0x78,
+        ];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["b"]["$bin"], "AP8=");
+        assert_eq!(parsed["s"], "x");
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_non_string_keys_roundtrip() {
+        // {1: "a", nil: [true]}
+        let bytes = vec![0x82, 0x01, 0xa1, 0x61, 0xc0, 0x91, 0xc3];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let options = DecodeOptions {
+            preserve_map_keys: true,
+            ..Default::default()
+        };
+        let json = msgpack_to_json(&base64, &options).unwrap().json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["$map"][0][0], 1);
+        assert_eq!(parsed["$map"][1][0], Value::Null);
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+
+        // Without the option, keys are stringified
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["1"], "a");
+        assert_eq!(parsed["null"][0], true);
+    }
+
+    #[test]
+    fn test_tag_named_keys_roundtrip() {
+        // {"$bin": "hello"}, {"$uint16": 5} and {"$map": []} as genuine maps
+        let mut bin = vec![0x81, 0xa4];
+        bin.extend_from_slice(b"$bin");
+        bin.push(0xa5);
+        bin.extend_from_slice(b"hello");
+        let mut uint16 = vec![0x81, 0xa7];
+        uint16.extend_from_slice(b"$uint16");
+        uint16.push(0x05);
+        let mut map = vec![0x81, 0xa4];
+        map.extend_from_slice(b"$map");
+        map.push(0x90);
+        for bytes in [bin, uint16, map] {
+            let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+            let json = msgpack_to_json(&base64, &DecodeOptions::default())
+                .unwrap()
+                .json;
+            let parsed: Value = serde_json::from_str(&json).unwrap();
+            assert!(parsed["$map"].is_array(), "{}", json);
+            assert_eq!(
+                json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+                base64
+            );
+        }
+
+        // Other `$` keys, and tag names next to other keys, stay objects
+        for (base64, expected) in [
+            ("gaQkZm9vAQ==", serde_json::json!({"$foo": 1})),
+            ("gqQkYmluAaFhAg==", serde_json::json!({"$bin": 1, "a": 2})),
+        ] {
+            let json = msgpack_to_json(base64, &DecodeOptions::default())
+                .unwrap()
+                .json;
+            assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+            assert_eq!(
+                json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+                base64
+            );
+        }
+    }
+
+    #[test]
+    fn test_wire_exact_roundtrip_preserves_bytes() {
+        let bytes = [
+            vec![0x89],
+            vec![0xa1, 0x61, 0xcf, 0, 0, 0, 0, 0, 0, 0, 1], // uint64 1
+            vec![0xa1, 0x62, 0xca, 0x3f, 0xc0, 0x00, 0x00], // float32 1.5
+            vec![0xa1, 0x63, 0xd9, 0x02, 0x68, 0x69],       // str8 "hi"
+            vec![0xa1, 0x64, 0xde, 0x00, 0x01, 0xa1, 0x78, 0xd1, 0x00, 0x05], // map16 {x: int16 5}
+            vec![0xa1, 0x65, 0xdd, 0, 0, 0, 1, 0xc0],       // array32 [nil]
+            vec![0xa1, 0x66, 0xc5, 0x00, 0x01, 0xff],       // bin16
+            vec![0xa1, 0x67, 0xc7, 0x04, 0x07, 1, 2, 3, 4], // ext8 with 4 bytes
+            vec![0xa1, 0x68, 0xd7, 0xff, 0, 0, 0, 0, 0, 0, 0, 1], // timestamp64 without nanos
+            vec![0xd9, 0x01, 0x69, 0x01],                   // str8 key
+        ]
+        .concat();
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let options = DecodeOptions {
+            wire_exact: true,
+            ..Default::default()
+        };
+
+        let json = msgpack_to_json(&base64, &options).unwrap().json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["$map"][0][1]["$uint64"], 1);
+        assert_eq!(parsed["$map"][2][1]["$str8"], "hi");
+        assert_eq!(parsed["$map"][8][0]["$str8"], "i");
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+
+        // Without annotations the narrowest formats are chosen instead
+        let plain = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        assert_ne!(
+            json_to_msgpack(&plain, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_wire_annotation_errors() {
+        assert!(json_to_msgpack(r#"{"$uint8": 300}"#, &EncodeOptions::default()).is_err());
+        assert!(json_to_msgpack(r#"{"$uint8": -1}"#, &EncodeOptions::default()).is_err());
+        assert!(json_to_msgpack(r#"{"$str8": 1}"#, &EncodeOptions::default()).is_err());
+        assert!(json_to_msgpack(r#"{"$map16": []}"#, &EncodeOptions::default()).is_err());
+        assert_eq!(
+            json_to_msgpack(r#"{"$int16": 1}"#, &EncodeOptions::default()).unwrap(),
+            "0QAB"
+        );
+    }
+
+    #[test]
+    fn test_float32_roundtrip() {
+        // [float32 0.1, float64 0.1]
+        let bytes = vec![
+            0x92, 0xca, 0x3d, 0xcc, 0xcc, 0xcd, 0xcb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99,
+            0x9a,
+        ];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0]["$float32"].to_string(), "0.1");
+        assert_eq!(parsed[1].to_string(), "0.1");
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_float32_output_option() {
+        let options = EncodeOptions {
+            float32: true,
+            ..EncodeOptions::default()
+        };
+        // [float32 0.1, 1]: integers are unaffected
+        assert_eq!(
+            json_to_msgpack("[0.1, 1]", &options).unwrap(),
+            "kso9zMzNAQ=="
+        );
+    }
+
+    #[test]
+    fn test_special_floats_roundtrip() {
+        let bytes = [
+            &[0x95, 0xcb][..],
+            &f64::NAN.to_be_bytes(),
+            &[0xcb],
+            &f64::NEG_INFINITY.to_be_bytes(),
+            &[0xcb],
+            &(-0.0f64).to_be_bytes(),
+            &[0xca],
+            &f32::INFINITY.to_be_bytes(),
+            &[0xca, 0x7f, 0x80, 0x00, 0x01], // signalling NaN
+        ]
+        .concat();
+        let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = msgpack_to_json(&base64, &DecodeOptions::default())
+            .unwrap()
+            .json;
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0]["$float64"], "NaN");
+        assert_eq!(parsed[1]["$float64"], "-Infinity");
+        assert_eq!(parsed[2].to_string(), "-0.0");
+        assert_eq!(parsed[3]["$float32"], "Infinity");
+        assert_eq!(parsed[4]["$float32"], "NaN:0x7f800001");
+        assert_eq!(
+            json_to_msgpack(&json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+        assert!(json_to_msgpack(r#"{"$float64": "nan"}"#, &EncodeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_base64_variants_accepted_and_preserved() {
+        // {"a": 255} as standard, base64url unpadded and wrapped base64
+        let expected = r#"{
+  "a": 255
+}"#;
+        for input in ["gaFhzP8=", "gaFhzP8", "gaFh\nzP8="] {
+            let decoded = msgpack_to_json(input, &DecodeOptions::default()).unwrap();
+            assert_eq!(decoded.json, expected, "input {:?}", input);
+        }
+        // [251, 255] base64url encodes as `-_8`
+        assert_eq!(base64_to_hex("-_8", HexLayout::Inline).unwrap(), "FB FF");
+
+        let (_, variant) = Base64Variant::decode("kszIzP8").unwrap();
+        let options = EncodeOptions {
+            base64: variant,
+            ..EncodeOptions::default()
+        };
+        assert_eq!(json_to_msgpack("[200, 255]", &options).unwrap(), "kszIzP8");
+        assert_eq!(hex_to_base64("FB FF", &variant).unwrap().0, "+/8");
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        // 1, {"a": "b"}, [true] back to back
+        let bytes = [0x01, 0x81, 0xa1, b'a', 0xa1, b'b', 0x91, 0xc3];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(bytes);
+
+        let ndjson = DecodeOptions {
+            stream: StreamFormat::Ndjson,
+            ..DecodeOptions::default()
+        };
+        let decoded = msgpack_to_json(&base64, &ndjson).unwrap();
+        assert_eq!(decoded.json, "1\n{\"a\":\"b\"}\n[true]");
+        let encode_ndjson = EncodeOptions {
+            stream: StreamFormat::Ndjson,
+            ..EncodeOptions::default()
+        };
+        assert_eq!(
+            json_to_msgpack(&decoded.json, &encode_ndjson).unwrap(),
+            base64
+        );
+
+        let array = DecodeOptions {
+            stream: StreamFormat::JsonArray,
+            ..DecodeOptions::default()
+        };
+        let decoded = msgpack_to_json(&base64, &array).unwrap();
+        let parsed: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(parsed, serde_json::json!([1, {"a": "b"}, [true]]));
+        let encode_array = EncodeOptions {
+            stream: StreamFormat::JsonArray,
+            ..EncodeOptions::default()
+        };
+        assert_eq!(
+            json_to_msgpack(&decoded.json, &encode_array).unwrap(),
+            base64
+        );
+        assert!(json_to_msgpack("{}", &encode_array).is_err());
+
+        // Single-value mode still stops after the first value
+        let single = msgpack_to_json(&base64, &DecodeOptions::default()).unwrap();
+        assert_eq!(single.json, "1");
+        assert_eq!(single.trailing, Some((1, 8)));
+        assert_eq!(
+            single.warnings,
+            vec!["7 trailing bytes after the top-level value at offset 1 (0x1)"]
+        );
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let options = EncodeOptions {
+            canonical: true,
+            ..Default::default()
+        };
+        let encode = |json: &str, options: &EncodeOptions| {
+            let base64 = json_to_msgpack(json, options).unwrap();
+            base64::engine::general_purpose::STANDARD
+                .decode(base64)
+                .unwrap()
+        };
+        let first = r#"{"b": 1, "aa": [1.5, 0.1], "a": {"$uint16": 7}}"#;
+        let second = r#"{"a": 7, "b": 1, "aa": [{"$float64": 1.5}, 0.1]}"#;
+        let expected: Vec<u8> = [
+            &[
+                0x83, 0xa1, b'a', 0x07, 0xa1, b'b', 0x01, 0xa2, b'a', b'a', 0x92,
+            ][..],
+            &[0xca, 0x3f, 0xc0, 0x00, 0x00],
+            &[0xcb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a],
+        ]
+        .concat();
+        assert_eq!(encode(first, &options), expected);
+        assert_eq!(encode(second, &options), expected);
+        assert_ne!(encode(first, &EncodeOptions::default()), expected);
+
+        // Pairs are sorted by key too, and every NaN is the float32 quiet NaN
+        assert_eq!(
+            encode(r#"{"$map": [[2, "x"], [1, "y"]]}"#, &options),
+            [0x82, 0x01, 0xa1, b'y', 0x02, 0xa1, b'x']
+        );
+        assert_eq!(
+            encode(r#"{"$float64": "NaN"}"#, &options),
+            [0xca, 0x7f, 0xc0, 0x00, 0x00]
+        );
+
+        // Equal numbers written differently give the same bytes; -0.0 stays a float
+        for json in ["100", "1e2", "100.0", r#"{"$float32": 100}"#] {
+            assert_eq!(encode(json, &options), [0x64], "{}", json);
+        }
+        assert_eq!(encode("-2.0e0", &options), [0xfe]);
+        assert_eq!(encode("-0.0", &options), [0xca, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_size_minimising_options() {
+        let json = r#"[2.0, -3.0, -0.0, 0.5, 0.1, {"$uint32": 7}]"#;
+        let options = EncodeOptions {
+            lossless_float32: true,
+            integral_floats_as_ints: true,
+            narrowest_headers: true,
+            ..Default::default()
+        };
+        assert!(options.minimizes());
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(json_to_msgpack(json, &options).unwrap())
+            .unwrap();
+        let expected: Vec<u8> = [
+            &[0x96, 0x02, 0xfd][..],
+            &[0xca, 0x80, 0x00, 0x00, 0x00],
+            &[0xca, 0x3f, 0x00, 0x00, 0x00],
+            &[0xcb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a],
+            &[0x07],
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+        assert_eq!(minimized_sizes(json, &options).unwrap(), (51, 23));
+
+        // 2^64 has no integer form, so it stays a float
+        let options = EncodeOptions {
+            integral_floats_as_ints: true,
+            ..Default::default()
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(json_to_msgpack("18446744073709551616.0", &options).unwrap())
+            .unwrap();
+        assert_eq!(bytes[0], 0xcb);
+        assert!(!EncodeOptions::default().minimizes());
+    }
+
+    #[test]
+    fn test_out_of_range_floats() {
+        for (json, expected) in [
+            ("1e400", "number 1e+400 at $ is outside the float64 range"),
+            ("-1e400", "number -1e+400 at $ is outside the float64 range"),
+            (
+                r#"{"x": [1.5e309]}"#,
+                "number 1.5e+309 at $.x[0] is outside the float64 range",
+            ),
+        ] {
+            let err = json_to_msgpack(json, &EncodeOptions::default()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Failed to encode msgpack: {}", expected)
+            );
+        }
+        // Even when big integers are allowed
+        let options = EncodeOptions {
+            big_int: BigIntEncoding::String,
+            ..Default::default()
+        };
+        assert!(json_to_msgpack("[1, 2e999]", &options).is_err());
+        assert_eq!(
+            json_to_msgpack("1e300", &EncodeOptions::default()).unwrap(),
+            "y3435DyIAHWc"
+        );
+    }
+
+    #[test]
+    fn test_big_integers() {
+        let json =
+            r#"{"ok": 18446744073709551615, "big": [-9223372036854775809, 18446744073709551616]}"#;
+        let err = json_to_msgpack(json, &EncodeOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to encode msgpack: integer -9223372036854775809 at $.big[0] is outside the 64-bit range; choose a big-integer encoding (string or ext) to keep it"
+        );
+
+        let as_string = EncodeOptions {
+            big_int: BigIntEncoding::String,
+            ..Default::default()
+        };
+        let msgpack = json_to_msgpack("[18446744073709551616]", &as_string).unwrap();
+        let decoded = msgpack_to_json(&msgpack, &DecodeOptions::default()).unwrap();
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(value, serde_json::json!(["18446744073709551616"]));
+
+        let as_ext = EncodeOptions {
+            big_int: BigIntEncoding::Ext(BigIntEncoding::DEFAULT_EXT_TYPE),
+            ..Default::default()
+        };
+        let msgpack = json_to_msgpack("18446744073709551616", &as_ext).unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&msgpack)
+            .unwrap();
+        assert_eq!(bytes, [0xc7, 0x09, 0x01, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Floats and negative zero are unaffected
+        let msgpack = json_to_msgpack("[1e30, -0]", &EncodeOptions::default()).unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&msgpack)
+            .unwrap();
+        assert_eq!(&bytes[10..], [0xcb, 0x80, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_big_int_to_bytes() {
+        assert_eq!(big_int_to_bytes("128"), [0x00, 0x80]);
+        assert_eq!(big_int_to_bytes("-128"), [0x80]);
+        assert_eq!(big_int_to_bytes("-129"), [0xff, 0x7f]);
+        assert_eq!(big_int_to_bytes("-256"), [0xff, 0x00]);
+        assert_eq!(
+            big_int_to_bytes("-9223372036854775809"),
+            [0xff, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_map_keys() {
+        // [{"id": 1, "id": 2}]
+        let base64 = base64::engine::general_purpose::STANDARD
+            .encode([0x91, 0x82, 0xa2, b'i', b'd', 0x01, 0xa2, b'i', b'd', 0x02]);
+        let decoded = msgpack_to_json(&base64, &DecodeOptions::default()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&decoded.json).unwrap(),
+            serde_json::json!([{"id": 2}])
+        );
+        assert_eq!(decoded.duplicate_keys, vec![(6, 9)]);
+        assert_eq!(
+            decoded.warnings,
+            vec![
+                r#"Map key "id" at offset 6 (0x6) repeats an earlier key; only the last value is shown"#
+            ]
+        );
+
+        let options = DecodeOptions {
+            keep_duplicate_keys: true,
+            ..Default::default()
+        };
+        let decoded = msgpack_to_json(&base64, &options).unwrap();
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(value, serde_json::json!([{"$map": [["id", 1], ["id", 2]]}]));
+        assert!(decoded.warnings[0]
+            .ends_with("all entries are kept as {\"$map\": [[key, value], ...]} pairs"));
+
+        // The pairs re-encode to the original bytes
+        let encoded = json_to_msgpack(&decoded.json, &EncodeOptions::default()).unwrap();
+        assert_eq!(encoded, base64);
+    }
+
+    #[test]
+    fn test_lint_wide_headers() {
+        // [str32 "hi", [uint16 1]] followed by a second value, uint8 2
+        let base64 = base64::engine::general_purpose::STANDARD.encode([
+            0x92, 0xdb, 0, 0, 0, 2, b'h', b'i', 0x91, 0xcd, 0, 1, 0xcc, 2,
+        ]);
+        let options = DecodeOptions {
+            lint: true,
+            stream: StreamFormat::JsonArray,
+            ..Default::default()
+        };
+        let decoded = msgpack_to_json(&base64, &options).unwrap();
+        let ranges: Vec<_> = decoded.wide_headers.iter().map(|h| h.range).collect();
+        assert_eq!(ranges, vec![(1, 6), (9, 12), (12, 14)]);
+        assert_eq!(
+            decoded.wide_headers[0].to_string(),
+            "str32 at offset 1 (0x1) declares length 2; fixstr would save 4 bytes"
+        );
+        assert!(decoded.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_disassembly() {
+        // {"id": uint32 7, "tags": ["x"]} followed by a bare 0xc1
+        let base64 = base64::engine::general_purpose::STANDARD.encode([
+            0x82, 0xa2, b'i', b'd', 0xce, 0, 0, 0, 7, 0xa4, b't', b'a', b'g', b's', 0x91, 0xa1,
+            b'x', 0xc1,
+        ]);
+        let listing = base64_to_disassembly(&base64, &DecodeOptions::default()).unwrap();
+        let expected = [
+            "00000000  82                          fixmap  len 2",
+            "00000001  A2                            fixstr  len 2  \"id\"",
+            "00000004  CE 00 00 00 07                uint32  7",
+            "00000009  A4                            fixstr  len 4  \"tags\"",
+            "0000000e  91                            fixarray  len 1",
+            "0000000f  A1                              fixstr  len 1  \"x\"",
+            "00000011  error: Invalid format byte 0xc1 at offset 17 ($)",
+        ];
+        assert_eq!(listing, expected.join("\n"));
+
+        // Long values are cut short
+        let base64 = json_to_msgpack(
+            &format!("\"{}\"", "a".repeat(100)),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let listing = base64_to_disassembly(&base64, &DecodeOptions::default()).unwrap();
+        assert!(listing.starts_with("00000000  D9 64                       str8  len 100  \"aaa"));
+        assert!(listing.ends_with("aaa…"));
+    }
+
+    #[test]
+    fn test_resync_skips_corrupt_bytes() {
+        let options = DecodeOptions {
+            resync: true,
+            ..Default::default()
+        };
+        let decode = |bytes: &[u8]| {
+            let base64 = base64::engine::general_purpose::STANDARD.encode(bytes);
+            msgpack_to_json(&base64, &options).unwrap()
+        };
+
+        // ["a", <0xc1 0xc1>, "b", 3]: the two unknown format bytes are skipped
+        let decoded = decode(&[0x94, 0xa1, b'a', 0xc1, 0xc1, 0xa1, b'b', 0x03, 0xc3]);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(value, serde_json::json!(["a", "b", 3, true]));
+        assert_eq!(decoded.skipped, vec![(3, 5)]);
+        assert!(decoded.warnings[0].starts_with("Skipped 2 corrupt bytes at offset 3"));
+
+        // A str 8 claiming 193 bytes is skipped over too. Resuming takes the first offset
+        // that decodes, so here the length byte (0xc1, never a valid format) goes as well.
+        let decoded = decode(&[0x82, 0xa1, b'k', 0xd9, 0xc1, 0x01, 0xa1, b'z', 0xc0]);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(value, serde_json::json!({"k": 1, "z": null}));
+        assert_eq!(decoded.skipped, vec![(3, 5)]);
+
+        // Without resync the same data is an error
+        let base64 = base64::engine::general_purpose::STANDARD.encode([0x92, 0xc1, 0x01]);
+        assert!(msgpack_to_json(&base64, &DecodeOptions::default()).is_err());
+        assert_eq!(
+            merge_ranges(vec![(4, 6), (1, 2), (2, 3)]),
+            vec![(1, 3), (4, 6)]
+        );
+    }
+
+    #[test]
+    fn test_partial_decode() {
+        let options = DecodeOptions {
+            partial: true,
+            ..Default::default()
+        };
+        let decode = |bytes: &[u8]| {
+            let base64 = base64::engine::general_purpose::STANDARD.encode(bytes);
+            msgpack_to_json(&base64, &options).unwrap()
+        };
+
+        // {"a": [1, "xy<cut>"], "b": 2} cut inside the string
+        let decoded = decode(&[0x82, 0xa1, b'a', 0x92, 0x01, 0xa4, b'x', b'y']);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"a": [1, {"$truncated": {"offset": 5, "expected": 5, "available": 3}}]})
+        );
+        assert_eq!(decoded.truncated, vec![(5, 8)]);
+        assert!(decoded.warnings[0].contains("offset 5 inside the value at $.a[1]"));
+
+        // Cut before a map value: the key is kept, and the whole map is highlighted
+        let decoded = decode(&[0x81, 0xa1, b'k']);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert!(value["k"]["$truncated"].is_object());
+        assert_eq!(decoded.truncated, vec![(0, 3)]);
+
+        // Cut inside a key: the key ends the map as a pair without a value
+        let decoded = decode(&[0x82, 0xa1, b'a', 0x01, 0xa3, b'x']);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"$map": [
+                ["a", 1],
+                [{"$truncated": {"offset": 4, "expected": 4, "available": 2}}]
+            ]})
+        );
+        assert_eq!(tagged::find_tagged(&value, tagged::TRUNCATED_TAG).len(), 1);
+        assert_eq!(decoded.truncated, vec![(4, 6)]);
+        let decoded = decode(&[0x81, 0xa3, b'x']);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"$map": [
+                [{"$truncated": {"offset": 1, "expected": 4, "available": 2}}]
+            ]})
+        );
+
+        // A lone truncated value becomes the marker
+        let decoded = decode(&[0xcd, 0x01]);
+        assert!(decoded.json.contains("$truncated"));
+        assert_eq!(decoded.truncated, vec![(0, 2)]);
+
+        // Containers claiming more elements than are left keep the ones that are there
+        let decoded = decode(&[0x93, 0x01, 0x02]);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([1, 2, {"$truncated": {"offset": 3, "expected": 1, "available": 0}}])
+        );
+        assert_eq!(decoded.truncated, vec![(0, 3)]);
+        let decoded = decode(&[0x92, 0x01]);
+        let value: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(value[0], 1);
+        assert!(value[1]["$truncated"].is_object());
+
+        // Other errors are still errors, and markers cannot be re-encoded
+        let base64 = base64::engine::general_purpose::STANDARD.encode([0x91, 0xc1]);
+        assert!(msgpack_to_json(&base64, &options).is_err());
+        assert!(json_to_msgpack(&decode(&[0x92, 0x01]).json, &EncodeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_depth: 2,
+            max_elements: 3,
+            max_size: 8,
+        };
+        let decode = |bytes: &[u8]| {
+            let base64 = base64::engine::general_purpose::STANDARD.encode(bytes);
+            let options = DecodeOptions {
+                limits,
+                ..Default::default()
+            };
+            match msgpack_to_json(&base64, &options) {
+                Err(ConvertError::Decode(e)) => e,
+                other => panic!("unexpected result {:?}", other),
+            }
+        };
+
+        // [[[1]]]
+        let e = decode(&[0x91, 0x91, 0x91, 0x01]);
+        assert_eq!((e.offset, e.path.as_str()), (2, "$[0][0]"));
+        assert!(e
+            .to_string()
+            .starts_with("Nesting depth of 3 exceeds the limit of 2"));
+        // [1, 2, 3, 4]
+        let e = decode(&[0x94, 0x01, 0x02, 0x03, 0x04]);
+        assert!(e
+            .to_string()
+            .starts_with("Element count of 4 exceeds the limit of 3"));
+        // Nine nils
+        let e = decode(&[0xc0; 9]);
+        assert_eq!(e.byte_range(), Some((8, 9)));
+
+        let options = EncodeOptions {
+            limits,
+            ..Default::default()
+        };
+        assert!(json_to_msgpack("[1,2,3]", &options).is_ok());
+        assert!(json_to_msgpack("\"too long\"", &options).is_err());
+        assert!(json_to_msgpack(r#"{"$bin":"AAAAAAAAAAA="}"#, &options).is_err());
+    }
+
+    #[test]
+    fn test_json_error_range() {
+        fn error_range(json: &str) -> Option<&str> {
+            json_to_msgpack(json, &EncodeOptions::default())
+                .unwrap_err()
+                .json_range()
+                .map(|(start, end)| &json[start..end])
+        }
+        // Unexpected character on a later line
+        assert_eq!(error_range("{\n  \"a\": 1,\n  \"b\": x\n}"), Some("x"));
+        // Trailing comma points at the closing brace
+        assert_eq!(error_range("[1, 2,]"), Some("]"));
+        // Multi-byte characters before the error
+        assert_eq!(error_range("{\"é\": tru}"), Some("}"));
+        // Truncated document points at its last character
+        assert_eq!(error_range("{\"a\": [1, 2\n\n"), Some("2"));
+        assert_eq!(
+            json_to_msgpack("", &EncodeOptions::default())
+                .unwrap_err()
+                .json_range(),
+            Some((0, 0))
+        );
+    }
+
+    #[test]
+    fn test_stream_reports_failing_value() {
+        let options = DecodeOptions {
+            stream: StreamFormat::Ndjson,
+            ..DecodeOptions::default()
+        };
+        // 1 followed by a truncated str 8
+        let err = msgpack_to_json("AdkF", &options).unwrap_err();
+        let ConvertError::Decode(decode) = &err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert_eq!((decode.offset, decode.path.as_str()), (1, "$[1]"));
+        assert_eq!(err.byte_range(), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_invalid_utf8_str_roundtrip() {
+        // {"name": "a\xffb", <invalid key "\xfe">: 1}
+        let bytes = [
+            0x82, 0xa4, b'n', b'a', b'm', b'e', 0xa3, b'a', 0xff, b'b', 0xa1, 0xfe, 0x01,
+        ];
+        let base64 = base64::engine::general_purpose::STANDARD.encode(bytes);
+        let decoded = msgpack_to_json(&base64, &DecodeOptions::default()).unwrap();
+        let parsed: Value = serde_json::from_str(&decoded.json).unwrap();
+        assert_eq!(parsed["$map"][0][1]["$rawstr"], "Yf9i");
+        assert_eq!(parsed["$map"][1][0]["$rawstr"], "/g==");
+        assert_eq!(decoded.warnings.len(), 2);
+        assert!(decoded.warnings[0].contains("$[\"$map\"][0][1]"));
+        assert_eq!(
+            json_to_msgpack(&decoded.json, &EncodeOptions::default()).unwrap(),
+            base64
+        );
+    }
+
+    #[test]
+    fn test_valid_utf8_has_no_warnings() {
+        let decoded = msgpack_to_json("o2jDqQ==", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.json, "\"hé\"");
+        assert!(decoded.warnings.is_empty());
+        assert_eq!(decoded.trailing, None);
+    }
+
     #[test]
     fn test_field_order_preserved() {
         // Test that field order is preserved during JSON conversion
         let json_with_order = r#"{"z": 1, "a": 2, "m": 3}"#;
-        let msgpack = json_to_msgpack(json_with_order).unwrap();
-        let back_to_json = msgpack_to_json(&msgpack).unwrap();
+        let msgpack = json_to_msgpack(json_with_order, &EncodeOptions::default()).unwrap();
+        let back_to_json = msgpack_to_json(&msgpack, &DecodeOptions::default())
+            .unwrap()
+            .json;
 
         // Parse and verify field order
         let parsed: Value = serde_json::from_str(&back_to_json).unwrap();
@@ -0,0 +1,271 @@
+use std::fmt;
+
+/// Why a msgpack value could not be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeErrorKind {
+    /// The value needs `expected` bytes from its offset but only `available` remain
+    Truncated { expected: usize, available: usize },
+    /// The format byte is not valid here (0xc1 is never used by msgpack)
+    InvalidFormat,
+    /// `actual` exceeds the configured maximum for `limit`
+    LimitExceeded {
+        limit: LimitKind,
+        max: usize,
+        actual: usize,
+    },
+}
+
+/// Which of the decode limits was exceeded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitKind {
+    /// Nesting depth of arrays and maps
+    Depth,
+    /// Number of elements declared by one array or map
+    Elements,
+    /// Size of the whole input in bytes
+    Size,
+}
+
+impl LimitKind {
+    fn label(&self) -> &'static str {
+        match self {
+            LimitKind::Depth => "Nesting depth",
+            LimitKind::Elements => "Element count",
+            LimitKind::Size => "Input size",
+        }
+    }
+}
+
+/// A msgpack decoding failure and where it happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    /// Offset of the format byte of the value that failed
+    pub offset: usize,
+    /// The format byte at `offset`, or `None` if the data ends before it
+    pub format_byte: Option<u8>,
+    /// JSON path of the failing value, e.g. `$.users[2].name`. Everything before the
+    /// last segment is the enclosing container.
+    pub path: String,
+    /// In partial mode, what the enclosing containers decoded before the data ran out
+    pub(crate) partial: Option<Box<serde_json::Value>>,
+}
+
+impl DecodeError {
+    /// The value at `offset` needs `expected` bytes in total but the data is shorter
+    pub fn truncated(data: &[u8], offset: usize, expected: usize) -> Self {
+        Self::new(
+            data,
+            offset,
+            DecodeErrorKind::Truncated {
+                expected,
+                available: data.len().saturating_sub(offset),
+            },
+        )
+    }
+
+    /// The format byte at `offset` is not valid
+    pub fn invalid_format(data: &[u8], offset: usize) -> Self {
+        Self::new(data, offset, DecodeErrorKind::InvalidFormat)
+    }
+
+    fn new(data: &[u8], offset: usize, kind: DecodeErrorKind) -> Self {
+        Self {
+            kind,
+            offset,
+            format_byte: data.get(offset).copied(),
+            path: "$".to_string(),
+            partial: None,
+        }
+    }
+
+    /// The value at `offset` exceeds a limit. Size limits are reported at the first
+    /// byte past the limit.
+    pub fn limit_exceeded(
+        data: &[u8],
+        offset: usize,
+        limit: LimitKind,
+        max: usize,
+        actual: usize,
+    ) -> Self {
+        Self::new(
+            data,
+            offset,
+            DecodeErrorKind::LimitExceeded { limit, max, actual },
+        )
+    }
+
+    /// Prefix the path with the array index the failing value sits at
+    pub fn within_index(mut self, index: usize) -> Self {
+        self.path.insert_str(1, &format!("[{}]", index));
+        self
+    }
+
+    /// Prefix the path with the map key the failing value sits under
+    pub fn within_key(mut self, key: &str) -> Self {
+        self.path.insert_str(1, &key_path_segment(key));
+        self
+    }
+
+    /// Byte range `(start, end)` of the bad bytes that are present in the data
+    pub fn byte_range(&self) -> Option<(usize, usize)> {
+        let len = match self.kind {
+            DecodeErrorKind::Truncated { available, .. } => available,
+            DecodeErrorKind::InvalidFormat | DecodeErrorKind::LimitExceeded { .. } => 1,
+        };
+        (len > 0).then_some((self.offset, self.offset + len))
+    }
+}
+
+/// JSON path segment for a map key: `.name` for identifiers, `["odd key"]` otherwise
+pub fn key_path_segment(key: &str) -> String {
+    let is_identifier =
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        format!(".{}", key)
+    } else {
+        format!("[{}]", serde_json::Value::String(key.to_string()))
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.kind, self.format_byte) {
+            (DecodeErrorKind::LimitExceeded { limit, max, actual }, _) => write!(
+                f,
+                "{} of {} exceeds the limit of {} at offset {} ({})",
+                limit.label(),
+                actual,
+                max,
+                self.offset,
+                self.path
+            ),
+            (_, None) => write!(
+                f,
+                "Unexpected end of data at offset {} ({})",
+                self.offset, self.path
+            ),
+            (
+                DecodeErrorKind::Truncated {
+                    expected,
+                    available,
+                },
+                Some(byte),
+            ) => write!(
+                f,
+                "Truncated value at offset {} (format byte 0x{:02x}, {}): expected {} bytes, {} available",
+                self.offset, byte, self.path, expected, available
+            ),
+            (DecodeErrorKind::InvalidFormat, Some(byte)) => write!(
+                f,
+                "Invalid format byte 0x{:02x} at offset {} ({})",
+                byte, self.offset, self.path
+            ),
+        }
+    }
+}
+
+/// Errors returned by the conversions in `converter`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    /// The Base64 text could not be decoded
+    InvalidBase64(String),
+    /// The hex text could not be parsed
+    InvalidHex(String),
+    /// The msgpack bytes could not be decoded
+    Decode(DecodeError),
+    /// The JSON text could not be parsed. `range` is the byte range of the character
+    /// the parser stopped at.
+    InvalidJson {
+        message: String,
+        range: (usize, usize),
+    },
+    /// A JSON value could not be encoded as msgpack
+    Encode(String),
+    /// The decoded value could not be written as JSON text
+    Serialize(String),
+}
+
+impl ConvertError {
+    /// Byte range of the msgpack data responsible for the error, if known
+    pub fn byte_range(&self) -> Option<(usize, usize)> {
+        match self {
+            ConvertError::Decode(e) => e.byte_range(),
+            _ => None,
+        }
+    }
+
+    /// Byte range of the JSON text responsible for the error, if known
+    pub fn json_range(&self) -> Option<(usize, usize)> {
+        match self {
+            ConvertError::InvalidJson { range, .. } => Some(*range),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::InvalidBase64(e) => write!(f, "Failed to decode base64: {}", e),
+            ConvertError::InvalidHex(e) => write!(f, "Invalid hex: {}", e),
+            ConvertError::Decode(e) => write!(f, "Failed to decode msgpack: {}", e),
+            ConvertError::InvalidJson { message, .. } => {
+                write!(f, "Failed to parse JSON: {}", message)
+            }
+            ConvertError::Encode(e) => write!(f, "Failed to encode msgpack: {}", e),
+            ConvertError::Serialize(e) => write!(f, "Failed to serialize JSON: {}", e),
+        }
+    }
+}
+
+impl From<DecodeError> for ConvertError {
+    fn from(e: DecodeError) -> Self {
+        ConvertError::Decode(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_error() {
+        // str 8 claiming 5 bytes with only 2 present
+        let data = [0x91, 0xd9, 0x05, 0x61, 0x62];
+        let e = DecodeError::truncated(&data, 1, 7)
+            .within_key("a b")
+            .within_index(3);
+        assert_eq!(e.path, r#"$[3]["a b"]"#);
+        assert_eq!(e.format_byte, Some(0xd9));
+        assert_eq!(
+            e.kind,
+            DecodeErrorKind::Truncated {
+                expected: 7,
+                available: 4
+            }
+        );
+        assert_eq!(e.byte_range(), Some((1, 5)));
+        assert_eq!(
+            e.to_string(),
+            r#"Truncated value at offset 1 (format byte 0xd9, $[3]["a b"]): expected 7 bytes, 4 available"#
+        );
+    }
+
+    #[test]
+    fn test_end_of_data_error() {
+        let e = DecodeError::truncated(&[0x92, 0x01], 2, 1).within_index(1);
+        assert_eq!(e.byte_range(), None);
+        assert_eq!(e.to_string(), "Unexpected end of data at offset 2 ($[1])");
+    }
+
+    #[test]
+    fn test_invalid_format_error() {
+        let e = DecodeError::invalid_format(&[0xc1], 0).within_key("items");
+        assert_eq!(e.byte_range(), Some((0, 1)));
+        assert_eq!(
+            ConvertError::from(e).to_string(),
+            "Failed to decode msgpack: Invalid format byte 0xc1 at offset 0 ($.items)"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use rmp::Marker;
+
+/// Broad kind of value a msgpack format encodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    Nil,
+    Bool,
+    Int,
+    Float,
+    Str,
+    Bin,
+    Array,
+    Map,
+    Ext,
+    Reserved,
+}
+
+/// Formats with an explicit size that can be requested through a `$<name>` annotation
+const SIZED_FORMATS: [Marker; 23] = [
+    Marker::U8,
+    Marker::U16,
+    Marker::U32,
+    Marker::U64,
+    Marker::I8,
+    Marker::I16,
+    Marker::I32,
+    Marker::I64,
+    Marker::F32,
+    Marker::F64,
+    Marker::Str8,
+    Marker::Str16,
+    Marker::Str32,
+    Marker::Bin8,
+    Marker::Bin16,
+    Marker::Bin32,
+    Marker::Array16,
+    Marker::Array32,
+    Marker::Map16,
+    Marker::Map32,
+    Marker::Ext8,
+    Marker::Ext16,
+    Marker::Ext32,
+];
+
+/// Name of a msgpack format as used in the spec (e.g. `fixmap`, `str8`, `uint32`)
+pub fn format_name(marker: Marker) -> &'static str {
+    match marker {
+        Marker::FixPos(_) => "positive fixint",
+        Marker::FixMap(_) => "fixmap",
+        Marker::FixArray(_) => "fixarray",
+        Marker::FixStr(_) => "fixstr",
+        Marker::Null => "nil",
+        Marker::Reserved => "never used",
+        Marker::False => "false",
+        Marker::True => "true",
+        Marker::Bin8 => "bin8",
+        Marker::Bin16 => "bin16",
+        Marker::Bin32 => "bin32",
+        Marker::Ext8 => "ext8",
+        Marker::Ext16 => "ext16",
+        Marker::Ext32 => "ext32",
+        Marker::F32 => "float32",
+        Marker::F64 => "float64",
+        Marker::U8 => "uint8",
+        Marker::U16 => "uint16",
+        Marker::U32 => "uint32",
+        Marker::U64 => "uint64",
+        Marker::I8 => "int8",
+        Marker::I16 => "int16",
+        Marker::I32 => "int32",
+        Marker::I64 => "int64",
+        Marker::FixExt1 => "fixext1",
+        Marker::FixExt2 => "fixext2",
+        Marker::FixExt4 => "fixext4",
+        Marker::FixExt8 => "fixext8",
+        Marker::FixExt16 => "fixext16",
+        Marker::Str8 => "str8",
+        Marker::Str16 => "str16",
+        Marker::Str32 => "str32",
+        Marker::Array16 => "array16",
+        Marker::Array32 => "array32",
+        Marker::Map16 => "map16",
+        Marker::Map32 => "map32",
+        Marker::FixNeg(_) => "negative fixint",
+    }
+}
+
+/// Look up a sized format by name (e.g. `uint16`, `str8`, `map32`)
+pub fn sized_format(name: &str) -> Option<Marker> {
+    SIZED_FORMATS
+        .iter()
+        .copied()
+        .find(|&marker| format_name(marker) == name)
+}
+
+/// The family a format belongs to
+pub fn family(marker: Marker) -> Family {
+    match marker {
+        Marker::Null => Family::Nil,
+        Marker::True | Marker::False => Family::Bool,
+        Marker::FixPos(_)
+        | Marker::FixNeg(_)
+        | Marker::U8
+        | Marker::U16
+        | Marker::U32
+        | Marker::U64
+        | Marker::I8
+        | Marker::I16
+        | Marker::I32
+        | Marker::I64 => Family::Int,
+        Marker::F32 | Marker::F64 => Family::Float,
+        Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32 => Family::Str,
+        Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => Family::Bin,
+        Marker::FixArray(_) | Marker::Array16 | Marker::Array32 => Family::Array,
+        Marker::FixMap(_) | Marker::Map16 | Marker::Map32 => Family::Map,
+        Marker::FixExt1
+        | Marker::FixExt2
+        | Marker::FixExt4
+        | Marker::FixExt8
+        | Marker::FixExt16
+        | Marker::Ext8
+        | Marker::Ext16
+        | Marker::Ext32 => Family::Ext,
+        Marker::Reserved => Family::Reserved,
+    }
+}
+
+/// The smallest marker for an integer, matching what the default encoder writes:
+/// unsigned formats for non-negative values and signed formats for negative ones.
+pub fn smallest_int_marker(value: i128) -> Marker {
+    match value {
+        0..=0x7f => Marker::FixPos(value as u8),
+        0x80..=0xff => Marker::U8,
+        0x100..=0xffff => Marker::U16,
+        0x1_0000..=0xffff_ffff => Marker::U32,
+        v if v >= 0 => Marker::U64,
+        -32..=-1 => Marker::FixNeg(value as i8),
+        -128..=-33 => Marker::I8,
+        -32768..=-129 => Marker::I16,
+        -2147483648..=-32769 => Marker::I32,
+        _ => Marker::I64,
+    }
+}
+
+/// The smallest marker of the same family (str, bin, array, map or ext) that can declare `len`.
+/// Returns the marker unchanged for other families.
+pub fn smallest_length_marker(marker: Marker, len: usize) -> Marker {
+    let width = match len {
+        0..=0xff => 8,
+        0x100..=0xffff => 16,
+        _ => 32,
+    };
+    match family(marker) {
+        Family::Str if len < 32 => Marker::FixStr(len as u8),
+        Family::Array if len < 16 => Marker::FixArray(len as u8),
+        Family::Map if len < 16 => Marker::FixMap(len as u8),
+        Family::Ext => match len {
+            1 => Marker::FixExt1,
+            2 => Marker::FixExt2,
+            4 => Marker::FixExt4,
+            8 => Marker::FixExt8,
+            16 => Marker::FixExt16,
+            _ => [Marker::Ext8, Marker::Ext16, Marker::Ext32][width / 16],
+        },
+        Family::Str => [Marker::Str8, Marker::Str16, Marker::Str32][width / 16],
+        Family::Bin => [Marker::Bin8, Marker::Bin16, Marker::Bin32][width / 16],
+        Family::Array => [Marker::Array16, Marker::Array16, Marker::Array32][width / 16],
+        Family::Map => [Marker::Map16, Marker::Map16, Marker::Map32][width / 16],
+        _ => marker,
+    }
+}
+
+/// Number of bytes a format takes before any payload or elements: the whole value for
+/// nil, bool, int and float, and the header including the ext type byte otherwise
+pub fn header_len(marker: Marker) -> usize {
+    match marker {
+        Marker::U8 | Marker::I8 | Marker::Str8 | Marker::Bin8 => 2,
+        Marker::FixExt1 | Marker::FixExt2 | Marker::FixExt4 | Marker::FixExt8 => 2,
+        Marker::FixExt16 => 2,
+        Marker::U16 | Marker::I16 | Marker::Str16 | Marker::Bin16 => 3,
+        Marker::Array16 | Marker::Map16 | Marker::Ext8 => 3,
+        Marker::Ext16 => 4,
+        Marker::U32 | Marker::I32 | Marker::F32 | Marker::Str32 | Marker::Bin32 => 5,
+        Marker::Array32 | Marker::Map32 => 5,
+        Marker::Ext32 => 6,
+        Marker::U64 | Marker::I64 | Marker::F64 => 9,
+        _ => 1,
+    }
+}
+
+/// Read `n` big-endian bytes starting at `start` as an unsigned number
+fn read_be(data: &[u8], start: usize, n: usize) -> Result<u64, String> {
+    let bytes = data.get(start..start + n).ok_or_else(|| {
+        format!(
+            "Truncated data: expected {} bytes at position {}, but only {} bytes available",
+            n,
+            start,
+            data.len().saturating_sub(start)
+        )
+    })?;
+    Ok(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+}
+
+fn marker_at(data: &[u8], pos: usize) -> Result<Marker, String> {
+    data.get(pos)
+        .map(|&b| Marker::from_u8(b))
+        .ok_or_else(|| format!("Unexpected end of data at position {}", pos))
+}
+
+/// Read the integer encoded at `pos`, which must use an integer format
+pub fn read_int(data: &[u8], pos: usize) -> Result<i128, String> {
+    Ok(match marker_at(data, pos)? {
+        Marker::FixPos(v) => v as i128,
+        Marker::FixNeg(v) => v as i128,
+        Marker::U8 => read_be(data, pos + 1, 1)? as i128,
+        Marker::U16 => read_be(data, pos + 1, 2)? as i128,
+        Marker::U32 => read_be(data, pos + 1, 4)? as i128,
+        Marker::U64 => read_be(data, pos + 1, 8)? as i128,
+        Marker::I8 => read_be(data, pos + 1, 1)? as i8 as i128,
+        Marker::I16 => read_be(data, pos + 1, 2)? as i16 as i128,
+        Marker::I32 => read_be(data, pos + 1, 4)? as i32 as i128,
+        Marker::I64 => read_be(data, pos + 1, 8)? as i64 as i128,
+        marker => {
+            return Err(format!(
+                "Expected an integer, found {}",
+                format_name(marker)
+            ))
+        }
+    })
+}
+
+/// Read the declared length of a str, bin, array, map or ext header at `pos`.
+/// Returns the length and the size of the header (including the ext type byte),
+/// or `None` for formats without a length.
+pub fn read_length_header(data: &[u8], pos: usize) -> Result<Option<(usize, usize)>, String> {
+    let marker = marker_at(data, pos)?;
+    let (len, size) = match marker {
+        Marker::FixStr(n) | Marker::FixArray(n) | Marker::FixMap(n) => (n as usize, 1),
+        Marker::Str8 | Marker::Bin8 => (read_be(data, pos + 1, 1)? as usize, 2),
+        Marker::Str16 | Marker::Bin16 | Marker::Array16 | Marker::Map16 => {
+            (read_be(data, pos + 1, 2)? as usize, 3)
+        }
+        Marker::Str32 | Marker::Bin32 | Marker::Array32 | Marker::Map32 => {
+            (read_be(data, pos + 1, 4)? as usize, 5)
+        }
+        Marker::FixExt1 => (1, 2),
+        Marker::FixExt2 => (2, 2),
+        Marker::FixExt4 => (4, 2),
+        Marker::FixExt8 => (8, 2),
+        Marker::FixExt16 => (16, 2),
+        Marker::Ext8 => (read_be(data, pos + 1, 1)? as usize, 3),
+        Marker::Ext16 => (read_be(data, pos + 1, 2)? as usize, 4),
+        Marker::Ext32 => (read_be(data, pos + 1, 4)? as usize, 6),
+        _ => return Ok(None),
+    };
+    Ok(Some((len, size)))
+}
+
+/// Write an integer using exactly the given integer format
+pub fn write_int(buf: &mut Vec<u8>, marker: Marker, value: i128) -> Result<(), String> {
+    let (min, max, width): (i128, i128, usize) = match marker {
+        Marker::U8 => (0, u8::MAX as i128, 1),
+        Marker::U16 => (0, u16::MAX as i128, 2),
+        Marker::U32 => (0, u32::MAX as i128, 4),
+        Marker::U64 => (0, u64::MAX as i128, 8),
+        Marker::I8 => (i8::MIN as i128, i8::MAX as i128, 1),
+        Marker::I16 => (i16::MIN as i128, i16::MAX as i128, 2),
+        Marker::I32 => (i32::MIN as i128, i32::MAX as i128, 4),
+        Marker::I64 => (i64::MIN as i128, i64::MAX as i128, 8),
+        _ => {
+            return Err(format!(
+                "{} is not a sized integer format",
+                format_name(marker)
+            ))
+        }
+    };
+    if !(min..=max).contains(&value) {
+        return Err(format!("{} does not fit in {}", value, format_name(marker)));
+    }
+    buf.push(marker.to_u8());
+    buf.extend_from_slice(&value.to_be_bytes()[16 - width..]);
+    Ok(())
+}
+
+/// Write a str, bin, array, map or ext header using exactly the given format.
+/// `ext_type` is written after the length for ext formats.
+pub fn write_length_header(
+    buf: &mut Vec<u8>,
+    marker: Marker,
+    len: usize,
+    ext_type: i8,
+) -> Result<(), String> {
+    let width = match marker {
+        Marker::Str8 | Marker::Bin8 | Marker::Ext8 => 1,
+        Marker::Str16 | Marker::Bin16 | Marker::Array16 | Marker::Map16 | Marker::Ext16 => 2,
+        Marker::Str32 | Marker::Bin32 | Marker::Array32 | Marker::Map32 | Marker::Ext32 => 4,
+        _ => {
+            return Err(format!(
+                "{} does not have a length field",
+                format_name(marker)
+            ))
+        }
+    };
+    if (len as u64) >> (width * 8) != 0 {
+        return Err(format!(
+            "length {} does not fit in {}",
+            len,
+            format_name(marker)
+        ));
+    }
+    buf.push(marker.to_u8());
+    buf.extend_from_slice(&(len as u64).to_be_bytes()[8 - width..]);
+    if family(marker) == Family::Ext {
+        buf.push(ext_type as u8);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sized_format_names_roundtrip() {
+        for marker in SIZED_FORMATS {
+            assert_eq!(sized_format(format_name(marker)), Some(marker));
+        }
+        assert_eq!(sized_format("fixstr"), None);
+        assert_eq!(sized_format("bogus"), None);
+    }
+
+    #[test]
+    fn test_smallest_markers() {
+        assert_eq!(smallest_int_marker(5), Marker::FixPos(5));
+        assert_eq!(smallest_int_marker(200), Marker::U8);
+        assert_eq!(smallest_int_marker(-1), Marker::FixNeg(-1));
+        assert_eq!(smallest_int_marker(-100), Marker::I8);
+        assert_eq!(smallest_int_marker(u64::MAX as i128), Marker::U64);
+        assert_eq!(smallest_length_marker(Marker::Str32, 3), Marker::FixStr(3));
+        assert_eq!(smallest_length_marker(Marker::Str8, 40), Marker::Str8);
+        assert_eq!(smallest_length_marker(Marker::Map32, 20), Marker::Map16);
+        assert_eq!(smallest_length_marker(Marker::Ext16, 4), Marker::FixExt4);
+        assert_eq!(smallest_length_marker(Marker::FixExt1, 3), Marker::Ext8);
+        assert_eq!(smallest_length_marker(Marker::Bin8, 70_000), Marker::Bin32);
+    }
+
+    #[test]
+    fn test_header_len() {
+        assert_eq!(header_len(Marker::FixPos(1)), 1);
+        assert_eq!(header_len(Marker::U64), 9);
+        assert_eq!(header_len(Marker::Str32), 5);
+        assert_eq!(header_len(Marker::FixExt4), 2);
+        assert_eq!(header_len(Marker::Ext32), 6);
+        // Headers written for a length match what is read back
+        for marker in SIZED_FORMATS {
+            if let Family::Str | Family::Bin | Family::Array | Family::Map | Family::Ext =
+                family(marker)
+            {
+                let mut buf = Vec::new();
+                write_length_header(&mut buf, marker, 1, 0).unwrap();
+                assert_eq!(header_len(marker), buf.len(), "{}", format_name(marker));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_headers() {
+        let mut buf = Vec::new();
+        write_int(&mut buf, Marker::U16, 1).unwrap();
+        assert_eq!(buf, vec![0xcd, 0x00, 0x01]);
+        assert_eq!(read_int(&buf, 0).unwrap(), 1);
+        assert!(write_int(&mut buf, Marker::I8, 200).is_err());
+
+        let mut buf = Vec::new();
+        write_length_header(&mut buf, Marker::Ext16, 3, -2).unwrap();
+        assert_eq!(buf, vec![0xc8, 0x00, 0x03, 0xfe]);
+        assert_eq!(read_length_header(&buf, 0).unwrap(), Some((3, 4)));
+        assert!(write_length_header(&mut buf, Marker::Str8, 300, 0).is_err());
+        assert_eq!(read_length_header(&[0xc0], 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,406 @@
+/// Layouts of pasted hex text that can be turned back into bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HexFormat {
+    /// Hex digits, optionally separated by whitespace (`81 A5` or `81a5`)
+    Plain,
+    /// `xxd` output: `00000000: 81a5 6865  ..he`
+    Xxd,
+    /// `hexdump -C` output: `00000000  81 a5 68 65  |..he|`
+    HexdumpCanonical,
+    /// Wireshark / tshark hex dump: `0000   81 a5 68 65   ..he`
+    Wireshark,
+    /// `0x`-prefixed bytes, including C and Rust array literals: `{0x81, 0xa5}`
+    PrefixedBytes,
+    /// Escaped string literal: `"\x81\xa5he"` or `b'\x81\xa5he'`
+    EscapedString,
+}
+
+impl HexFormat {
+    /// Human-readable name of the layout
+    pub fn label(&self) -> &'static str {
+        match self {
+            HexFormat::Plain => "plain hex",
+            HexFormat::Xxd => "xxd dump",
+            HexFormat::HexdumpCanonical => "hexdump -C dump",
+            HexFormat::Wireshark => "Wireshark hex dump",
+            HexFormat::PrefixedBytes => "0x-prefixed bytes",
+            HexFormat::EscapedString => "\\x escape string",
+        }
+    }
+}
+
+/// Extract the bytes from hex text in any supported layout, reporting which one it was
+pub fn parse_hex_input(text: &str) -> Result<(Vec<u8>, HexFormat), String> {
+    // Dumps come first since their ASCII column may contain anything
+    if let Some(parsed) = parse_offset_dump(text) {
+        return Ok(parsed);
+    }
+    let trimmed = text.trim_start();
+    let is_literal = trimmed.starts_with(['"', '\'', '('])
+        || trimmed.starts_with("b\"")
+        || trimmed.starts_with("b'");
+    if is_literal || text.contains("\\x") || text.contains("\\X") {
+        return parse_escaped(text).map(|bytes| (bytes, HexFormat::EscapedString));
+    }
+    if text.contains("0x") || text.contains("0X") {
+        return parse_prefixed(text).map(|bytes| (bytes, HexFormat::PrefixedBytes));
+    }
+    if is_empty_array(text) {
+        return Ok((Vec::new(), HexFormat::PrefixedBytes));
+    }
+    parse_plain(text).map(|bytes| (bytes, HexFormat::Plain))
+}
+
+/// Parse hex digits with any whitespace between them
+fn parse_plain(text: &str) -> Result<Vec<u8>, String> {
+    let clean_hex: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if !clean_hex.len().is_multiple_of(2) {
+        return Err("Hex string must have an even number of characters".to_string());
+    }
+
+    // Validate hex characters
+    if !clean_hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Invalid hex characters".to_string());
+    }
+
+    // Safe to slice by byte index since we validated the characters are ASCII above
+    (0..clean_hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&clean_hex[i..i + 2], 16).map_err(|_| "Invalid hex byte".to_string())
+        })
+        .collect()
+}
+
+/// Whether `text` is one of the empty array literals the literal export produces:
+/// `&[]`, `vec![]`, `[]byte{}`, `new Uint8Array([])` or `const uint8_t data[0] = {};`
+fn is_empty_array(text: &str) -> bool {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let compact = compact.strip_suffix(';').unwrap_or(&compact);
+    match compact.strip_suffix("[0]={}") {
+        // Any C declaration, whose type and name run together once spaces are gone
+        Some(declaration) => {
+            !declaration.is_empty()
+                && declaration
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => ["&[]", "vec![]", "[]byte{}", "newUint8Array([])"].contains(&compact),
+    }
+}
+
+/// Parse `0x81, 0xa5` style byte lists, ignoring surrounding array syntax.
+/// A single long `0x` token such as `0x81a5` is read as a hex stream.
+fn parse_prefixed(text: &str) -> Result<Vec<u8>, String> {
+    let lower = text.to_ascii_lowercase();
+    let tokens: Vec<&str> = lower
+        .split("0x")
+        .skip(1)
+        .map(|rest| {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(rest.len());
+            &rest[..digits]
+        })
+        .collect();
+    match tokens.as_slice() {
+        [stream] if stream.len() > 2 => parse_plain(stream),
+        _ => tokens
+            .iter()
+            .map(|token| match token.len() {
+                1 | 2 => u8::from_str_radix(token, 16).map_err(|_| "Invalid hex byte".to_string()),
+                0 => Err("Found 0x without hex digits".to_string()),
+                _ => Err(format!("0x{} does not fit in a byte", token)),
+            })
+            .collect(),
+    }
+}
+
+/// Parse string literals with `\xHH` escapes; other characters stand for their UTF-8 bytes
+fn parse_escaped(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for body in literal_bodies(text.trim())? {
+        unescape_into(body, &mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// The contents of the quoted literals in `text`. Adjacent literals such as
+/// `(b"\x81" b"\xa5")` or `"\x81" + "\xa5"` are all returned in order, and text
+/// without quotes is a single bare body.
+fn literal_bodies(text: &str) -> Result<Vec<&str>, String> {
+    if !text.contains(['"', '\'']) {
+        return Ok(vec![text]);
+    }
+    let mut bodies = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || "()+,;".contains(c));
+        if rest.is_empty() {
+            return Ok(bodies);
+        }
+        // An optional `b` prefix, as in Python bytes literals
+        rest = rest.strip_prefix(['b', 'B']).unwrap_or(rest);
+        let quote = rest
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+            .ok_or_else(|| "Unexpected text outside a string literal".to_string())?;
+        let inner = &rest[1..];
+        let mut escaped = false;
+        let end = inner
+            .char_indices()
+            .find(|&(_, c)| {
+                let closes = !escaped && c == quote;
+                escaped = !escaped && c == '\\';
+                closes
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(|| "Unterminated string literal".to_string())?;
+        bodies.push(&inner[..end]);
+        rest = &inner[end + 1..];
+    }
+}
+
+/// Append the bytes of one literal body, resolving escapes
+fn unescape_into(body: &str, bytes: &mut Vec<u8>) -> Result<(), String> {
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        let escaped = chars
+            .next()
+            .ok_or_else(|| "Escape string ends with a lone backslash".to_string())?;
+        let byte = match escaped {
+            'x' | 'X' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(format!("Invalid escape \\x{}", digits));
+                }
+                u8::from_str_radix(&digits, 16).map_err(|_| "Invalid hex byte".to_string())?
+            }
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            '\\' | '\'' | '"' => escaped as u8,
+            other => return Err(format!("Unsupported escape \\{}", other)),
+        };
+        bytes.push(byte);
+    }
+    Ok(())
+}
+
+/// Parse a dump whose lines start with an offset (xxd, hexdump -C, Wireshark).
+/// Returns `None` unless every line's offset matches the bytes before it and some line
+/// has bytes after its offset, so plain hex that happens to start with a 4-digit group
+/// or consists of zeros is never mistaken for a dump.
+fn parse_offset_dump(text: &str) -> Option<(Vec<u8>, HexFormat)> {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let first = lines.first()?.trim_start();
+    let format = if first.split_whitespace().next()?.ends_with(':') {
+        HexFormat::Xxd
+    } else if text.contains('|') {
+        HexFormat::HexdumpCanonical
+    } else {
+        HexFormat::Wireshark
+    };
+
+    let mut bytes = Vec::new();
+    let mut repeat_previous = false;
+    let mut previous_line: Vec<u8> = Vec::new();
+    for line in lines {
+        let line = line.trim_start();
+        // hexdump collapses identical lines into `*`
+        if line.trim() == "*" {
+            repeat_previous = true;
+            continue;
+        }
+        let offset_len = line
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(line.len());
+        if offset_len < 4 {
+            return None;
+        }
+        let offset = usize::from_str_radix(&line[..offset_len], 16).ok()?;
+        let mut rest = &line[offset_len..];
+        if format == HexFormat::Xxd {
+            rest = rest.strip_prefix(':')?;
+        } else if !rest.is_empty() && !rest.starts_with("  ") {
+            // Offsets are followed by at least two spaces, unlike groups of plain hex
+            return None;
+        }
+        if repeat_previous {
+            if previous_line.is_empty() {
+                return None;
+            }
+            while bytes.len() < offset {
+                bytes.extend_from_slice(&previous_line);
+            }
+            repeat_previous = false;
+        }
+        if offset != bytes.len() {
+            return None;
+        }
+
+        // Cut off the ASCII column
+        let hex_area = match format {
+            HexFormat::Xxd => rest.trim_start().split("  ").next()?,
+            HexFormat::HexdumpCanonical => rest.split('|').next()?,
+            _ => rest.trim_start().split("   ").next()?,
+        };
+        let line_bytes = parse_plain(hex_area).ok()?;
+        bytes.extend_from_slice(&line_bytes);
+        if !line_bytes.is_empty() {
+            previous_line = line_bytes;
+        }
+    }
+    // Offsets with no bytes after them are plain hex, e.g. `0000` or `00000000`
+    if bytes.is_empty() {
+        return None;
+    }
+    Some((bytes, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: [u8; 8] = [0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b];
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(
+            parse_hex_input("81 A5 68 65\n6C 6C 6F 7B"),
+            Ok((HELLO.to_vec(), HexFormat::Plain))
+        );
+        assert_eq!(
+            parse_hex_input("81a568656c6c6f7b"),
+            Ok((HELLO.to_vec(), HexFormat::Plain))
+        );
+        // Runs of zeros are not mistaken for offset lines without bytes
+        for zeros in ["0000", "00000000", "0000000000000000"] {
+            assert_eq!(
+                parse_hex_input(zeros),
+                Ok((vec![0; zeros.len() / 2], HexFormat::Plain))
+            );
+        }
+        // 4-digit groups are not mistaken for offsets
+        assert_eq!(
+            parse_hex_input("81a5 6865 6c6c 6f7b"),
+            Ok((HELLO.to_vec(), HexFormat::Plain))
+        );
+        assert_eq!(
+            parse_hex_input("0000 0001"),
+            Ok((vec![0, 0, 0, 1], HexFormat::Plain))
+        );
+        assert!(parse_hex_input("81 A").is_err());
+        assert!(parse_hex_input("zz").is_err());
+    }
+
+    #[test]
+    fn test_xxd() {
+        let dump = "00000000: 81a5 6865 6c6c 6f7b 0001 0203 0405 0607  ..hello{\\x0.....\n\
+                    00000010: 0809                                     ..\n";
+        let mut expected = HELLO.to_vec();
+        expected.extend(0u8..10);
+        assert_eq!(parse_hex_input(dump), Ok((expected, HexFormat::Xxd)));
+    }
+
+    #[test]
+    fn test_hexdump_canonical() {
+        let dump = "00000000  81 a5 68 65 6c 6c 6f 7b  00 00 00 00 00 00 00 00  |..hello{........|\n\
+                    00000010  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+                    *\n\
+                    00000040  ab cd                                             |..|\n\
+                    00000042\n";
+        let (bytes, format) = parse_hex_input(dump).unwrap();
+        assert_eq!(format, HexFormat::HexdumpCanonical);
+        assert_eq!(bytes.len(), 0x42);
+        assert_eq!(&bytes[..8], &HELLO);
+        assert!(bytes[8..0x40].iter().all(|&b| b == 0));
+        assert_eq!(&bytes[0x40..], &[0xab, 0xcd]);
+    }
+
+    #[test]
+    fn test_wireshark() {
+        let dump = "0000   81 a5 68 65 6c 6c 6f 7b   ..hello{\n";
+        assert_eq!(
+            parse_hex_input(dump),
+            Ok((HELLO.to_vec(), HexFormat::Wireshark))
+        );
+    }
+
+    #[test]
+    fn test_prefixed_bytes() {
+        let c_array =
+            "unsigned char data[8] = {0x81, 0xa5, 0x68, 0x65,\n  0x6c, 0x6c, 0x6f, 0x7B};";
+        assert_eq!(
+            parse_hex_input(c_array),
+            Ok((HELLO.to_vec(), HexFormat::PrefixedBytes))
+        );
+        assert_eq!(
+            parse_hex_input("0x81a568656c6c6f7b"),
+            Ok((HELLO.to_vec(), HexFormat::PrefixedBytes))
+        );
+        assert_eq!(
+            parse_hex_input("[0x1, 0xa]"),
+            Ok((vec![0x01, 0x0a], HexFormat::PrefixedBytes))
+        );
+        assert_eq!(
+            parse_hex_input("const uint8_t data[0] = {\n};"),
+            Ok((vec![], HexFormat::PrefixedBytes))
+        );
+        assert_eq!(
+            parse_hex_input("new Uint8Array([])"),
+            Ok((vec![], HexFormat::PrefixedBytes))
+        );
+        assert_eq!(
+            parse_hex_input("unsigned char data[0] = {}"),
+            Ok((vec![], HexFormat::PrefixedBytes))
+        );
+        assert!(parse_hex_input("0x81a5, 0x01").is_err());
+        // Only complete empty literals stand for no bytes
+        for garbage in [
+            "data[0]",
+            "81 a5 []",
+            "{}",
+            "[]",
+            "foo {} bar",
+            "&[] 81",
+            "x = [0]={};",
+        ] {
+            assert!(parse_hex_input(garbage).is_err(), "{}", garbage);
+        }
+    }
+
+    #[test]
+    fn test_escaped_string() {
+        assert_eq!(
+            parse_hex_input(r#""\x81\xa5hello{""#),
+            Ok((HELLO.to_vec(), HexFormat::EscapedString))
+        );
+        assert_eq!(
+            parse_hex_input(r"b'\x81\xA5hello{'"),
+            Ok((HELLO.to_vec(), HexFormat::EscapedString))
+        );
+        assert_eq!(
+            parse_hex_input(r"\x00\n\\"),
+            Ok((vec![0x00, b'\n', b'\\'], HexFormat::EscapedString))
+        );
+        assert_eq!(
+            parse_hex_input("(\n    b\"\\x81\\xa5hel\"\n    b'lo{'\n)"),
+            Ok((HELLO.to_vec(), HexFormat::EscapedString))
+        );
+        assert_eq!(
+            parse_hex_input(r#""a\"b""#),
+            Ok((b"a\"b".to_vec(), HexFormat::EscapedString))
+        );
+        assert!(parse_hex_input(r"\x8").is_err());
+        assert!(parse_hex_input(r#""\x81"#).is_err());
+    }
+}
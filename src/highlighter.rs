@@ -56,29 +56,108 @@ pub fn highlight_json(code: &str) -> String {
     escape_html(code)
 }
 
-/// Highlight hex code with optional range highlighting
-pub fn highlight_hex(code: &str, highlight_range: Option<(usize, usize)>) -> String {
+/// Wrap the text at byte range `start..end` of the original code in a span with `class`,
+/// given HTML highlighted from that code. Entities count as the character they stand for,
+/// and the span is closed and reopened around tags inside the range so it stays well nested.
+pub fn mark_html_range(html: &str, start: usize, end: usize, class: &str) -> String {
+    let open = format!(r#"<span class="{}">"#, class);
+    let mut marked = String::with_capacity(html.len() + open.len() + 7);
+    let mut offset = 0;
+    let mut inside = false;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let tag_len = rest.find('>').map_or(rest.len(), |i| i + 1);
+            if inside {
+                marked.push_str("</span>");
+            }
+            marked.push_str(&rest[..tag_len]);
+            if inside {
+                marked.push_str(&open);
+            }
+            rest = &rest[tag_len..];
+            continue;
+        }
+        let (html_len, code_len) = match rest.find(';') {
+            Some(semi) if c == '&' && semi <= 10 => (semi + 1, entity_len(&rest[1..semi])),
+            _ => (c.len_utf8(), c.len_utf8()),
+        };
+        if !inside && start <= offset && offset < end {
+            marked.push_str(&open);
+            inside = true;
+        }
+        marked.push_str(&rest[..html_len]);
+        rest = &rest[html_len..];
+        offset += code_len;
+        if inside && offset >= end {
+            marked.push_str("</span>");
+            inside = false;
+        }
+    }
+    if inside {
+        marked.push_str("</span>");
+    }
+    marked
+}
+
+/// UTF-8 length of the character an HTML entity name such as `lt` or `#233` stands for
+fn entity_len(name: &str) -> usize {
+    let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => name.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+    };
+    code.and_then(char::from_u32).map_or(1, char::len_utf8)
+}
+
+/// Highlight hex code with character ranges, each wrapped in a span of its CSS class
+/// such as `hex-highlight` for the current selection or `hex-error` for bytes that
+/// failed to decode. Where ranges overlap, the span carries all of their classes.
+pub fn highlight_hex(code: &str, ranges: &[((usize, usize), &str)]) -> String {
     if code.is_empty() {
         return String::new();
     }
 
-    match highlight_range {
-        Some((char_start, char_end)) => {
-            if char_start > char_end || char_end > code.len() {
-                // Invalid range: fallback to escaped HTML
-                escape_html(code)
-            } else {
-                let before = escape_html(&code[..char_start]);
-                let highlighted = escape_html(&code[char_start..char_end]);
-                let after = escape_html(&code[char_end..]);
-                format!(
-                    r#"{}<span class="hex-highlight">{}</span>{}"#,
-                    before, highlighted, after
-                )
+    // Invalid ranges are ignored
+    let is_valid = |&(start, end): &(usize, usize)| {
+        start <= end
+            && end <= code.len()
+            && code.is_char_boundary(start)
+            && code.is_char_boundary(end)
+    };
+    let ranges: Vec<((usize, usize), &str)> = ranges
+        .iter()
+        .copied()
+        .filter(|(range, _)| is_valid(range))
+        .collect();
+
+    let mut bounds = vec![0, code.len()];
+    for ((start, end), _) in &ranges {
+        bounds.extend([*start, *end]);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut html = String::new();
+    for segment in bounds.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let mut classes: Vec<&str> = Vec::new();
+        for ((s, e), class) in &ranges {
+            if *s <= start && end <= *e && !classes.contains(class) {
+                classes.push(class);
             }
         }
-        None => escape_html(code),
+        let text = escape_html(&code[start..end]);
+        if classes.is_empty() {
+            html.push_str(&text);
+        } else {
+            html.push_str(&format!(
+                r#"<span class="{}">{}</span>"#,
+                classes.join(" "),
+                text
+            ));
+        }
     }
+    html
 }
 
 #[cfg(test)]
@@ -111,35 +190,122 @@ mod tests {
 
     #[test]
     fn test_highlight_hex_empty() {
-        assert_eq!(highlight_hex("", None), "");
-        assert_eq!(highlight_hex("", Some((0, 0))), "");
+        assert_eq!(highlight_hex("", &[]), "");
+        assert_eq!(highlight_hex("", &[((0, 0), "hex-highlight")]), "");
     }
 
     #[test]
     fn test_highlight_hex_no_range() {
-        assert_eq!(highlight_hex("81 A5", None), "81 A5");
+        assert_eq!(highlight_hex("81 A5", &[]), "81 A5");
     }
 
     #[test]
     fn test_highlight_hex_with_range() {
-        let result = highlight_hex("81 A5 68", Some((3, 5)));
+        let result = highlight_hex("81 A5 68", &[((3, 5), "hex-highlight")]);
         assert!(result.contains(r#"<span class="hex-highlight">A5</span>"#));
     }
 
     #[test]
     fn test_highlight_hex_invalid_range() {
         // char_start > char_end
-        assert_eq!(highlight_hex("81 A5", Some((5, 3))), "81 A5");
+        assert_eq!(
+            highlight_hex("81 A5", &[((5, 3), "hex-highlight")]),
+            "81 A5"
+        );
         // char_end > code.len()
-        assert_eq!(highlight_hex("81 A5", Some((0, 100))), "81 A5");
+        assert_eq!(
+            highlight_hex("81 A5", &[((0, 100), "hex-highlight")]),
+            "81 A5"
+        );
     }
 
     #[test]
     fn test_highlight_hex_escapes_content() {
-        let result = highlight_hex("<script>", Some((0, 4)));
+        let result = highlight_hex("<script>", &[((0, 4), "hex-highlight")]);
         assert!(result.contains("&lt;scr"));
     }
 
+    #[test]
+    fn test_highlight_hex_trailing_overlapping_selection() {
+        let result = highlight_hex(
+            "01 02 03",
+            &[((0, 5), "hex-highlight"), ((3, 8), "hex-trailing")],
+        );
+        assert_eq!(
+            result,
+            concat!(
+                r#"<span class="hex-highlight">01 </span>"#,
+                r#"<span class="hex-highlight hex-trailing">02</span>"#,
+                r#"<span class="hex-trailing"> 03</span>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_highlight_hex_error_range() {
+        assert_eq!(
+            highlight_hex("92 01 C1", &[((6, 8), "hex-error")]),
+            r#"92 01 <span class="hex-error">C1</span>"#
+        );
+    }
+
+    #[test]
+    fn test_highlight_hex_lint_range() {
+        assert_eq!(
+            highlight_hex(
+                "CF 00 00",
+                &[((0, 5), "hex-highlight"), ((0, 2), "hex-lint")]
+            ),
+            concat!(
+                r#"<span class="hex-highlight hex-lint">CF</span>"#,
+                r#"<span class="hex-highlight"> 00</span> 00"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_highlight_hex_repeated_class() {
+        let ranges = [
+            ((0, 5), "hex-highlight"),
+            ((0, 2), "hex-lint"),
+            ((0, 2), "hex-highlight"),
+        ];
+        assert_eq!(
+            highlight_hex("CF 00", &ranges),
+            r#"<span class="hex-highlight hex-lint">CF</span><span class="hex-highlight"> 00</span>"#
+        );
+    }
+
+    #[test]
+    fn test_highlight_hex_multiple_ranges() {
+        let result = highlight_hex(
+            "41 42  |AB|",
+            &[((3, 5), "hex-highlight"), ((9, 10), "hex-highlight")],
+        );
+        assert_eq!(
+            result,
+            r#"41 <span class="hex-highlight">42</span>  |A<span class="hex-highlight">B</span>|"#
+        );
+    }
+
+    #[test]
+    fn test_mark_html_range() {
+        let html = r#"<span class="token">"a&amp;b"</span>: 1"#;
+        // Byte range of `b": 1` in the code `"a&b": 1`
+        assert_eq!(
+            mark_html_range(html, 3, 8, "json-error"),
+            concat!(
+                r#"<span class="token">"a&amp;<span class="json-error">b""#,
+                r#"</span></span><span class="json-error">: 1</span>"#
+            )
+        );
+        assert_eq!(
+            mark_html_range("&#233;x", 2, 3, "json-error"),
+            r#"&#233;<span class="json-error">x</span>"#
+        );
+        assert_eq!(mark_html_range("ab", 1, 1, "json-error"), "ab");
+    }
+
     // Note: highlight_json tests are only available in wasm32 target
     // since they require PrismJS/browser environment
     #[cfg(target_arch = "wasm32")]
@@ -2,14 +2,27 @@ use leptos::prelude::*;
 use wasm_bindgen::prelude::wasm_bindgen;
 use web_sys::HtmlTextAreaElement;
 
+mod base64_variant;
 mod converter;
+mod error;
+mod format;
+mod hex_input;
 mod highlighter;
+mod literal;
 mod position_mapper;
+mod tagged;
 
-use converter::{base64_to_hex, hex_to_base64, json_to_msgpack, msgpack_to_json};
-use highlighter::{highlight_hex, highlight_json};
+use base64_variant::Base64Variant;
+use converter::{
+    base64_to_disassembly, base64_to_hex, base64_to_literal, hex_to_base64, json_to_msgpack,
+    minimized_sizes, msgpack_to_json, BigIntEncoding, EncodeOptions,
+};
+use hex_input::HexFormat;
+use highlighter::{highlight_hex, highlight_json, mark_html_range};
+use literal::LiteralLanguage;
 use position_mapper::{
-    byte_range_to_hex_char_range, create_position_mappings, find_hex_range_for_json_selection,
+    byte_range_to_hex_char_range, create_position_mappings, find_hex_ranges_for_json_selection,
+    DecodeOptions, HexLayout, Limits, StreamFormat, WideHeader,
 };
 
 #[wasm_bindgen(start)]
@@ -25,16 +38,39 @@ fn App() -> impl IntoView {
     let (msgpack_hex, set_msgpack_hex) = signal(String::new());
     let (json_input, set_json_input) = signal(String::new());
     let (error, set_error) = signal(String::new());
-    let (hex_highlight_range, set_hex_highlight_range) = signal(Option::<(usize, usize)>::None);
+    let (warnings, set_warnings) = signal(Vec::<String>::new());
+    let (trailing_range, set_trailing_range) = signal(Option::<(usize, usize)>::None);
+    let (error_ranges, set_error_ranges) = signal(Vec::<(usize, usize)>::new());
+    let (wide_headers, set_wide_headers) = signal(Vec::<WideHeader>::new());
+    let (json_error_range, set_json_error_range) = signal(Option::<(usize, usize)>::None);
+    let (hex_source, set_hex_source) = signal(Option::<HexFormat>::None);
+    let (literal_language, set_literal_language) = signal(LiteralLanguage::RustSlice);
+    let (literal_width, set_literal_width) = signal(80usize);
+    let (hex_highlight_range, set_hex_highlight_range) = signal(Vec::<(usize, usize)>::new());
+    let (hex_layout, set_hex_layout) = signal(HexLayout::Inline);
+    let (show_disassembly, set_show_disassembly) = signal(false);
+    let (decode_options, set_decode_options) = signal(DecodeOptions::default());
+    let (encode_options, set_encode_options) = signal(EncodeOptions::default());
+    // Encoded size without and with the size-minimising options, after To MsgPack
+    let (encoded_sizes, set_encoded_sizes) = signal(Option::<(usize, usize)>::None);
 
     // Handle base64 input change - update hex in real-time
     let handle_base64_change = move |value: String| {
         set_msgpack_base64.set(value.clone());
+        set_trailing_range.set(None);
+        set_error_ranges.set(Vec::new());
+        set_wide_headers.set(Vec::new());
+        set_json_error_range.set(None);
+        set_hex_source.set(None);
         if !value.trim().is_empty() {
-            match base64_to_hex(value.trim()) {
+            match base64_to_hex(value.trim(), hex_layout.get()) {
                 Ok(hex) => {
                     set_msgpack_hex.set(hex);
                     set_error.set(String::new());
+                    // Remember the pasted flavour so generated Base64 matches it
+                    if let Ok((_, variant)) = Base64Variant::decode(value.trim()) {
+                        set_encode_options.update(|options| options.base64 = variant);
+                    }
                 }
                 Err(_) => {
                     // Don't update hex if base64 is invalid
@@ -48,9 +84,28 @@ fn App() -> impl IntoView {
     // Handle hex input change - update base64 in real-time
     let handle_hex_change = move |value: String| {
         set_msgpack_hex.set(value.clone());
+        set_trailing_range.set(None);
+        set_error_ranges.set(Vec::new());
+        set_wide_headers.set(Vec::new());
         if !value.trim().is_empty() {
-            match hex_to_base64(value.trim()) {
-                Ok(base64) => {
+            match hex_to_base64(value.trim(), &encode_options.get().base64) {
+                Ok((base64, format)) => {
+                    // Rewrite other layouts in the current one so byte positions line up
+                    // for highlighting. Dump highlights rely on exact row widths, so a
+                    // pasted dump is always re-rendered, even one in the same format.
+                    let layout = hex_layout.get();
+                    let native = match layout {
+                        HexLayout::Inline => HexFormat::Plain,
+                        HexLayout::Dump => HexFormat::HexdumpCanonical,
+                    };
+                    if format != native || layout == HexLayout::Dump {
+                        if let Ok(hex) = base64_to_hex(&base64, layout) {
+                            set_msgpack_hex.set(hex);
+                        }
+                    }
+                    if format != native {
+                        set_hex_source.set(Some(format));
+                    }
                     set_msgpack_base64.set(base64);
                     set_error.set(String::new());
                 }
@@ -60,19 +115,37 @@ fn App() -> impl IntoView {
             }
         } else {
             set_msgpack_base64.set(String::new());
+            set_hex_source.set(None);
         }
     };
 
     // Handle MsgPack to JSON conversion
     let handle_msgpack_to_json = move |_| {
         set_error.set(String::new());
+        set_warnings.set(Vec::new());
+        set_trailing_range.set(None);
+        set_error_ranges.set(Vec::new());
+        set_wide_headers.set(Vec::new());
         let base64 = msgpack_base64.get();
-        match msgpack_to_json(base64.trim()) {
-            Ok(json) => {
-                set_json_input.set(json);
+        match msgpack_to_json(base64.trim(), &decode_options.get()) {
+            Ok(decoded) => {
+                set_json_input.set(decoded.json);
+                set_warnings.set(decoded.warnings);
+                set_trailing_range.set(decoded.trailing);
+                // Highlight the bytes that could not be decoded
+                set_error_ranges.set(
+                    decoded
+                        .skipped
+                        .into_iter()
+                        .chain(decoded.truncated)
+                        .collect(),
+                );
+                set_wide_headers.set(decoded.wide_headers);
             }
             Err(e) => {
-                set_error.set(e);
+                // Point at the bytes that failed to decode
+                set_error_ranges.set(e.byte_range().into_iter().collect());
+                set_error.set(e.to_string());
             }
         }
     };
@@ -80,28 +153,54 @@ fn App() -> impl IntoView {
     // Handle JSON to MsgPack conversion
     let handle_json_to_msgpack = move |_| {
         set_error.set(String::new());
+        set_warnings.set(Vec::new());
+        set_error_ranges.set(Vec::new());
+        set_wide_headers.set(Vec::new());
+        set_json_error_range.set(None);
+        set_encoded_sizes.set(None);
         let json = json_input.get();
-        match json_to_msgpack(json.trim()) {
+        let options = encode_options.get();
+        // Untrimmed, so error positions line up with the editor
+        match json_to_msgpack(&json, &options) {
             Ok(msgpack) => {
+                if options.minimizes() {
+                    set_encoded_sizes.set(minimized_sizes(&json, &options).ok());
+                }
                 set_msgpack_base64.set(msgpack.clone());
+                set_hex_source.set(None);
                 // Also update hex display
-                if let Ok(hex) = base64_to_hex(&msgpack) {
+                if let Ok(hex) = base64_to_hex(&msgpack, hex_layout.get()) {
                     set_msgpack_hex.set(hex);
                 }
             }
             Err(e) => {
-                set_error.set(e);
+                set_json_error_range.set(e.json_range());
+                set_error.set(e.to_string());
             }
         }
     };
 
+    // Decoding and encoding share the same limits
+    let update_limits = move |update: fn(&mut Limits, usize), value: String| {
+        if let Ok(value) = value.parse::<usize>() {
+            set_decode_options.update(|options| update(&mut options.limits, value));
+            set_encode_options.update(|options| update(&mut options.limits, value));
+        }
+    };
+
     // Clear all fields
     let clear_all = move |_| {
         set_msgpack_base64.set(String::new());
         set_msgpack_hex.set(String::new());
         set_json_input.set(String::new());
         set_error.set(String::new());
-        set_hex_highlight_range.set(None);
+        set_warnings.set(Vec::new());
+        set_trailing_range.set(None);
+        set_error_ranges.set(Vec::new());
+        set_wide_headers.set(Vec::new());
+        set_hex_source.set(None);
+        set_hex_highlight_range.set(Vec::new());
+        set_encoded_sizes.set(None);
     };
 
     // Handle JSON selection changes to highlight corresponding hex bytes
@@ -110,25 +209,18 @@ fn App() -> impl IntoView {
         let json = json_input.get();
 
         if base64.is_empty() || json.is_empty() || sel_start == sel_end {
-            set_hex_highlight_range.set(None);
+            set_hex_highlight_range.set(Vec::new());
             return;
         }
 
         // Decode base64 to bytes
-        if let Ok(bytes) =
-            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, base64.trim())
-        {
-            let mappings = create_position_mappings(&bytes, &json);
-            if let Some(byte_range) =
-                find_hex_range_for_json_selection(&mappings, sel_start, sel_end)
-            {
-                let char_range = byte_range_to_hex_char_range(byte_range.0, byte_range.1);
-                set_hex_highlight_range.set(Some(char_range));
-            } else {
-                set_hex_highlight_range.set(None);
-            }
+        if let Ok((bytes, _)) = Base64Variant::decode(base64.trim()) {
+            let mappings = create_position_mappings(&bytes, &json, &decode_options.get());
+            set_hex_highlight_range.set(find_hex_ranges_for_json_selection(
+                &mappings, sel_start, sel_end,
+            ));
         } else {
-            set_hex_highlight_range.set(None);
+            set_hex_highlight_range.set(Vec::new());
         }
     };
 
@@ -138,17 +230,55 @@ fn App() -> impl IntoView {
         if json.is_empty() {
             String::new()
         } else {
-            highlight_json(&json)
+            let html = highlight_json(&json);
+            match json_error_range.get() {
+                Some((start, end)) => mark_html_range(&html, start, end, "json-error"),
+                None => html,
+            }
+        }
+    });
+
+    let literal_output = Memo::new(move |_| {
+        let base64 = msgpack_base64.get();
+        base64_to_literal(base64.trim(), literal_language.get(), literal_width.get())
+            .unwrap_or_default()
+    });
+
+    // Only list the tokens while the panel is open; it is rebuilt on every keystroke
+    let disassembly_output = Memo::new(move |_| {
+        if !show_disassembly.get() {
+            return String::new();
         }
+        let base64 = msgpack_base64.get();
+        base64_to_disassembly(base64.trim(), &decode_options.get()).unwrap_or_default()
     });
 
     let hex_highlighted = Memo::new(move |_| {
         let hex = msgpack_hex.get();
-        let range = hex_highlight_range.get();
+        let layout = hex_layout.get();
+        let char_ranges = |ranges: &[(usize, usize)], class: &'static str| {
+            ranges
+                .iter()
+                .flat_map(|&(start, end)| byte_range_to_hex_char_range(start, end, layout))
+                .map(move |range| (range, class))
+                .collect::<Vec<_>>()
+        };
         if hex.is_empty() {
             String::new()
         } else {
-            highlight_hex(&hex, range)
+            let lint_ranges: Vec<(usize, usize)> = wide_headers
+                .get()
+                .iter()
+                .map(|header| header.range)
+                .collect();
+            let ranges = [
+                char_ranges(&hex_highlight_range.get(), "hex-highlight"),
+                char_ranges(trailing_range.get().as_slice(), "hex-trailing"),
+                char_ranges(&error_ranges.get(), "hex-error"),
+                char_ranges(&lint_ranges, "hex-lint"),
+            ]
+            .concat();
+            highlight_hex(&hex, &ranges)
         }
     });
 
@@ -175,6 +305,304 @@ fn App() -> impl IntoView {
                 }
             }}
 
+            {move || {
+                warnings
+                    .get()
+                    .into_iter()
+                    .map(|warning| {
+                        view! {
+                            <div class="warning-banner">
+                                <span class="warning-icon">"⚠️"</span>
+                                {warning}
+                            </div>
+                        }
+                    })
+                    .collect_view()
+            }}
+
+            {move || {
+                let headers = wide_headers.get();
+                (!headers.is_empty()).then(|| {
+                    let saved: usize = headers.iter().map(WideHeader::saved).sum();
+                    view! {
+                        <div class="lint-report">
+                            <div class="lint-summary">
+                                <span class="warning-icon">"📏"</span>
+                                {format!(
+                                    "{} header{} wider than needed; re-encoding would save {} byte{}",
+                                    headers.len(),
+                                    if headers.len() == 1 { " is" } else { "s are" },
+                                    saved,
+                                    if saved == 1 { "" } else { "s" },
+                                )}
+                            </div>
+                            <ul class="lint-list">
+                                {headers
+                                    .iter()
+                                    .map(|header| view! { <li>{header.to_string()}</li> })
+                                    .collect_view()}
+                            </ul>
+                        </div>
+                    }
+                })
+            }}
+
+            {move || {
+                trailing_range.get().map(|_| {
+                    view! {
+                        <div class="warning-banner">
+                            <span class="warning-icon">"➕"</span>
+                            "The extra bytes may be further MsgPack values."
+                            <button
+                                class="warning-action"
+                                on:click=move |ev| {
+                                    set_decode_options.update(|options| options.stream = StreamFormat::JsonArray);
+                                    set_encode_options.update(|options| options.stream = StreamFormat::JsonArray);
+                                    handle_msgpack_to_json(ev);
+                                }
+                            >
+                                "Decode all values"
+                            </button>
+                        </div>
+                    }
+                })
+            }}
+
+            <div class="options-bar">
+                <label class="option-toggle" title="Show maps with non-string keys as {\"$map\": [[key, value], ...]} so they re-encode unchanged">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().preserve_map_keys
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.preserve_map_keys = checked);
+                        }
+                    />
+                    "Preserve non-string map keys"
+                </label>
+                <label class="option-toggle" title="Annotate values not stored in their smallest format (e.g. {\"$uint16\": 1}) so unmodified values re-encode byte-identically">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().wire_exact
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.wire_exact = checked);
+                        }
+                    />
+                    "Wire-exact formats"
+                </label>
+                <label class="option-toggle" title="Decode cut-off data as far as it goes, marking where it ends with {\"$truncated\": ...}, instead of failing">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().partial
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.partial = checked);
+                        }
+                    />
+                    "Recover truncated data"
+                </label>
+                <label class="option-toggle" title="Skip over corrupt bytes (unknown format bytes, impossible lengths) and resume decoding at the next offset where a value decodes">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().resync
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.resync = checked);
+                        }
+                    />
+                    "Skip corrupt bytes"
+                </label>
+                <label class="option-toggle" title="List every header wider than its value needs (e.g. uint64 for a small number, str32 for a short string) and highlight it in the hex view">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().lint
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.lint = checked);
+                        }
+                    />
+                    "Lint headers"
+                </label>
+                <label class="option-toggle" title="Show maps with repeated keys as {\"$map\": [[key, value], ...]} pairs so every entry is visible, instead of keeping only the last value">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || decode_options.get().keep_duplicate_keys
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_decode_options.update(|options| options.keep_duplicate_keys = checked);
+                        }
+                    />
+                    "Keep duplicate keys"
+                </label>
+                <label class="option-toggle" title="Encode floats without a $float32/$float64 tag as float32 instead of float64">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || encode_options.get().float32
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_encode_options.update(|options| options.float32 = checked);
+                        }
+                    />
+                    "Encode floats as float32"
+                </label>
+                <label class="option-toggle" title="Encode equal documents to identical bytes: map keys sorted by their encoded bytes, the smallest header for every value (wire-format annotations are ignored), integral floats as integers and other floats as float32 whenever that is exact">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || encode_options.get().canonical
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_encode_options.update(|options| options.canonical = checked);
+                        }
+                    />
+                    "Canonical encoding"
+                </label>
+                <label class="option-toggle" title="Encode floats without a $float32/$float64 tag as float32 when that loses no precision">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || encode_options.get().lossless_float32
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_encode_options.update(|options| options.lossless_float32 = checked);
+                        }
+                    />
+                    "float32 when lossless"
+                </label>
+                <label class="option-toggle" title="Encode floats with an integral value, such as 2.0, as integers">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || encode_options.get().integral_floats_as_ints
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_encode_options.update(|options| options.integral_floats_as_ints = checked);
+                        }
+                    />
+                    "Integral floats as ints"
+                </label>
+                <label class="option-toggle" title="Ignore wire-format annotations such as {\"$uint16\": 1} so every value gets its smallest header">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || encode_options.get().narrowest_headers
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            set_encode_options.update(|options| options.narrowest_headers = checked);
+                        }
+                    />
+                    "Narrowest headers"
+                </label>
+                <label class="option-toggle" title="How To MsgPack encodes JSON integers above u64::MAX or below i64::MIN, which no msgpack integer format can hold">
+                    "Big integers"
+                    <select
+                        class="option-select"
+                        prop:value=move || match encode_options.get().big_int {
+                            BigIntEncoding::Error => "error",
+                            BigIntEncoding::String => "string",
+                            BigIntEncoding::Ext(_) => "ext",
+                        }
+                        on:change=move |ev| {
+                            let big_int = match event_target_value(&ev).as_str() {
+                                "string" => BigIntEncoding::String,
+                                "ext" => BigIntEncoding::Ext(BigIntEncoding::DEFAULT_EXT_TYPE),
+                                _ => BigIntEncoding::Error,
+                            };
+                            set_encode_options.update(|options| options.big_int = big_int);
+                        }
+                    >
+                        <option value="error">"Reject"</option>
+                        <option value="string">"As string"</option>
+                        <option value="ext">"As ext type 1"</option>
+                    </select>
+                </label>
+                <label class="option-toggle" title="Show hex as hexdump -C style rows of 16 bytes with offsets and an ASCII column">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || hex_layout.get() == HexLayout::Dump
+                        on:change=move |ev| {
+                            let layout = if event_target_checked(&ev) {
+                                HexLayout::Dump
+                            } else {
+                                HexLayout::Inline
+                            };
+                            set_hex_layout.set(layout);
+                            let base64 = msgpack_base64.get();
+                            if let Ok(hex) = base64_to_hex(base64.trim(), layout) {
+                                set_msgpack_hex.set(hex);
+                                set_hex_source.set(None);
+                            }
+                        }
+                    />
+                    "Hexdump view"
+                </label>
+                <label class="option-toggle" title="Show a disassembly panel listing every MsgPack token with its offset, header bytes, format, declared length and decoded value">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || show_disassembly.get()
+                        on:change=move |ev| set_show_disassembly.set(event_target_checked(&ev))
+                    />
+                    "Disassembly"
+                </label>
+                <label class="option-toggle" title="Treat the MsgPack buffer as back-to-back values, shown as one JSON array or as newline-delimited JSON; To MsgPack concatenates the values again">
+                    "Stream"
+                    <select
+                        class="option-select"
+                        prop:value=move || match decode_options.get().stream {
+                            StreamFormat::Off => "off",
+                            StreamFormat::JsonArray => "array",
+                            StreamFormat::Ndjson => "ndjson",
+                        }
+                        on:change=move |ev| {
+                            let stream = match event_target_value(&ev).as_str() {
+                                "array" => StreamFormat::JsonArray,
+                                "ndjson" => StreamFormat::Ndjson,
+                                _ => StreamFormat::Off,
+                            };
+                            set_decode_options.update(|options| options.stream = stream);
+                            set_encode_options.update(|options| options.stream = stream);
+                        }
+                    >
+                        <option value="off">"Single value"</option>
+                        <option value="array">"JSON array"</option>
+                        <option value="ndjson">"NDJSON"</option>
+                    </select>
+                </label>
+                <label class="option-toggle" title="Maximum nesting depth of arrays and maps; deeper input is rejected instead of exhausting the stack">
+                    "Max depth"
+                    <input
+                        type="number"
+                        class="option-select limit-input"
+                        min="1"
+                        prop:value=move || decode_options.get().limits.max_depth.to_string()
+                        on:change=move |ev| {
+                            update_limits(|limits, value| limits.max_depth = value, event_target_value(&ev));
+                        }
+                    />
+                </label>
+                <label class="option-toggle" title="Maximum number of elements a single array or map may declare">
+                    "Max elements"
+                    <input
+                        type="number"
+                        class="option-select limit-input"
+                        min="0"
+                        prop:value=move || decode_options.get().limits.max_elements.to_string()
+                        on:change=move |ev| {
+                            update_limits(|limits, value| limits.max_elements = value, event_target_value(&ev));
+                        }
+                    />
+                </label>
+                <label class="option-toggle" title="Maximum size of the MsgPack data, and of JSON text being converted, in KiB">
+                    "Max KiB"
+                    <input
+                        type="number"
+                        class="option-select limit-input"
+                        min="1"
+                        prop:value=move || (decode_options.get().limits.max_size / 1024).to_string()
+                        on:change=move |ev| {
+                            update_limits(|limits, value| limits.max_size = value.saturating_mul(1024), event_target_value(&ev));
+                        }
+                    />
+                </label>
+            </div>
+
             <main class="converter-container">
                 <div class="input-section">
                     <div class="msgpack-inputs">
@@ -182,6 +610,15 @@ fn App() -> impl IntoView {
                             <label for="msgpack-base64-input" class="input-label">
                                 <span class="label-icon">"📦"</span>
                                 "Base64 MsgPack"
+                                {move || {
+                                    (!msgpack_base64.get().trim().is_empty()).then(|| {
+                                        view! {
+                                            <span class="variant-badge" title="Detected Base64 variant, also used for generated Base64">
+                                                {encode_options.get().base64.label()}
+                                            </span>
+                                        }
+                                    })
+                                }}
                             </label>
                             <textarea
                                 id="msgpack-base64-input"
@@ -197,16 +634,90 @@ fn App() -> impl IntoView {
                         <div class="msgpack-input-group">
                             <label for="msgpack-hex-input" class="input-label">
                                 <span class="label-icon">"🔢"</span>
-                                "Hex (Space-separated)"
+                                {move || match hex_layout.get() {
+                                    HexLayout::Inline => "Hex (Space-separated)",
+                                    HexLayout::Dump => "Hex (hexdump -C)",
+                                }}
+                                {move || {
+                                    hex_source.get().map(|format| {
+                                        view! {
+                                            <span class="variant-badge" title="Detected layout of the pasted hex, converted to plain hex">
+                                                {format!("from {}", format.label())}
+                                            </span>
+                                        }
+                                    })
+                                }}
                             </label>
                             <HexHighlighter
                                 id="msgpack-hex-input"
                                 value=msgpack_hex
                                 highlighted_html=hex_highlighted
                                 on_change=move |value: String| handle_hex_change(value)
-                                placeholder="Or paste hex bytes here (e.g., 81 A5 68 65 6C 6C 6F)..."
+                                placeholder="Or paste hex bytes here (e.g., 81 A5 68 65 6C 6C 6F), or an xxd / hexdump -C / Wireshark dump, 0x81, 0xa5 or \\x81\\xa5..."
                             />
                         </div>
+                        <div class="msgpack-input-group">
+                            <label for="literal-output" class="input-label">
+                                <span class="label-icon">"📤"</span>
+                                "Source literal"
+                                <select
+                                    class="option-select"
+                                    on:change=move |ev| {
+                                        let index = event_target_value(&ev).parse::<usize>().unwrap_or_default();
+                                        set_literal_language.set(LiteralLanguage::ALL[index % LiteralLanguage::ALL.len()]);
+                                    }
+                                >
+                                    {LiteralLanguage::ALL
+                                        .iter()
+                                        .enumerate()
+                                        .map(|(index, language)| {
+                                            view! { <option value=index.to_string()>{language.label()}</option> }
+                                        })
+                                        .collect_view()}
+                                </select>
+                                <input
+                                    type="number"
+                                    class="option-select literal-width"
+                                    min="0"
+                                    title="Line width (0 keeps the literal on one line)"
+                                    prop:value=move || literal_width.get().to_string()
+                                    on:change=move |ev| {
+                                        if let Ok(width) = event_target_value(&ev).parse::<usize>() {
+                                            set_literal_width.set(width);
+                                        }
+                                    }
+                                />
+                            </label>
+                            <textarea
+                                id="literal-output"
+                                class="input-area msgpack-textarea"
+                                readonly
+                                spellcheck="false"
+                                placeholder="The bytes as a source-code literal appear here; paste one into the hex field to load it back"
+                                prop:value=move || literal_output.get()
+                            />
+                        </div>
+                        {move || {
+                            show_disassembly.get().then(|| {
+                                view! {
+                                    <div class="msgpack-input-group">
+                                        <label for="disassembly-output" class="input-label">
+                                            <span class="label-icon">"🔬"</span>
+                                            "Disassembly"
+                                        </label>
+                                        <textarea
+                                            id="disassembly-output"
+                                            class="input-area msgpack-textarea disassembly-output"
+                                            readonly
+                                            spellcheck="false"
+                                            wrap="off"
+                                            placeholder="Every MsgPack token appears here: offset, header bytes, format, declared length and decoded value, indented by nesting depth"
+                                            prop:value=move || disassembly_output.get()
+                                        />
+                                    </div>
+                                }
+                            })
+                        }}
                     </div>
                 </div>
 
@@ -229,6 +740,17 @@ fn App() -> impl IntoView {
                         <span class="button-icon">"⬅️"</span>
                         <span class="button-text">"To MsgPack"</span>
                     </button>
+                    {move || {
+                        encoded_sizes
+                            .get()
+                            .map(|(before, after)| {
+                                view! {
+                                    <div class="size-comparison" title="Encoded size without → with the size-minimising options">
+                                        {format!("{} → {} bytes", before, after)}
+                                    </div>
+                                }
+                            })
+                    }}
                     <button
                         class="clear-button"
                         on:click=clear_all
@@ -247,7 +769,11 @@ fn App() -> impl IntoView {
                         id="json-input"
                         value=json_input
                         highlighted_html=json_highlighted
-                        on_change=move |value: String| set_json_input.set(value)
+                        error_range=json_error_range
+                        on_change=move |value: String| {
+                            set_json_input.set(value);
+                            set_json_error_range.set(None);
+                        }
                         on_selection_change=handle_json_selection_change
                         placeholder="Paste JSON data here..."
                     />
@@ -319,6 +845,7 @@ fn JsonHighlighter(
     #[prop(into)] id: String,
     value: ReadSignal<String>,
     highlighted_html: Memo<String>,
+    error_range: ReadSignal<Option<(usize, usize)>>,
     on_change: impl Fn(String) + 'static,
     on_selection_change: impl Fn(usize, usize) + 'static,
     #[prop(into)] placeholder: String,
@@ -330,6 +857,18 @@ fn JsonHighlighter(
     let on_selection_change_clone2 = on_selection_change.clone();
     let on_selection_change_clone3 = on_selection_change.clone();
     let overlay_ref: NodeRef<leptos::html::Pre> = NodeRef::new();
+    let textarea_ref: NodeRef<leptos::html::Textarea> = NodeRef::new();
+
+    // Move the cursor to a syntax error so it scrolls into view
+    Effect::new(move |_| {
+        if let (Some((start, _)), Some(textarea)) = (error_range.get(), textarea_ref.get()) {
+            // The textarea counts positions in UTF-16 code units
+            let text = value.get_untracked();
+            let cursor = text.get(..start).unwrap_or(&text).encode_utf16().count() as u32;
+            let _ = textarea.focus();
+            let _ = textarea.set_selection_range(cursor, cursor);
+        }
+    });
 
     let handle_select = move |ev: web_sys::Event| {
         let target = event_target::<HtmlTextAreaElement>(&ev);
@@ -382,6 +921,7 @@ fn JsonHighlighter(
             <textarea
                 id=id
                 class="json-textarea"
+                node_ref=textarea_ref
                 placeholder=placeholder
                 prop:value=move || value.get()
                 on:input=move |ev| {
@@ -0,0 +1,193 @@
+/// Programming languages the payload bytes can be exported to as a source literal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LiteralLanguage {
+    /// `&[0x81, 0xa5]`
+    RustSlice,
+    /// `vec![0x81, 0xa5]`
+    RustVec,
+    /// `const uint8_t data[2] = {0x81, 0xa5};`
+    C,
+    /// `b"\x81\xa5"`
+    Python,
+    /// `[]byte{0x81, 0xa5}`
+    Go,
+    /// `new Uint8Array([0x81, 0xa5])`
+    JavaScript,
+}
+
+impl LiteralLanguage {
+    pub const ALL: [LiteralLanguage; 6] = [
+        LiteralLanguage::RustSlice,
+        LiteralLanguage::RustVec,
+        LiteralLanguage::C,
+        LiteralLanguage::Python,
+        LiteralLanguage::Go,
+        LiteralLanguage::JavaScript,
+    ];
+
+    /// Human-readable name of the literal form
+    pub fn label(&self) -> &'static str {
+        match self {
+            LiteralLanguage::RustSlice => "Rust &[u8]",
+            LiteralLanguage::RustVec => "Rust vec![]",
+            LiteralLanguage::C => "C array",
+            LiteralLanguage::Python => "Python bytes",
+            LiteralLanguage::Go => "Go []byte",
+            LiteralLanguage::JavaScript => "JavaScript Uint8Array",
+        }
+    }
+}
+
+/// Indentation of continuation lines when a literal is wrapped
+const INDENT: &str = "    ";
+
+/// Render bytes as a source literal. Lines are wrapped to at most `line_width`
+/// characters where possible; a width of 0 keeps the literal on one line.
+/// Every form can be pasted back into the hex input.
+pub fn render_literal(bytes: &[u8], language: LiteralLanguage, line_width: usize) -> String {
+    if language == LiteralLanguage::Python {
+        return render_python(bytes, line_width);
+    }
+    let c_open = format!("const uint8_t data[{}] = {{", bytes.len());
+    let (open, close) = match language {
+        LiteralLanguage::RustSlice => ("&[", "]"),
+        LiteralLanguage::RustVec => ("vec![", "]"),
+        LiteralLanguage::C => (c_open.as_str(), "};"),
+        LiteralLanguage::Go => ("[]byte{", "}"),
+        _ => ("new Uint8Array([", "])"),
+    };
+    let items: Vec<String> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+
+    let single_line = format!("{}{}{}", open, items.join(", "), close);
+    if bytes.is_empty() || line_width == 0 || single_line.len() <= line_width {
+        return single_line;
+    }
+    // Every wrapped line ends with a comma, which Go requires
+    let lines = wrap(&items, line_width.saturating_sub(INDENT.len()), |line| {
+        format!("{},", line.join(", "))
+    });
+    format!("{}\n{}\n{}", open, indent(&lines), close)
+}
+
+/// Render bytes as a Python bytes literal, using adjacent literals in parentheses when wrapped
+fn render_python(bytes: &[u8], line_width: usize) -> String {
+    let pieces: Vec<String> = bytes
+        .iter()
+        .map(|&b| match b {
+            b'\\' => "\\\\".to_string(),
+            b'"' => "\\\"".to_string(),
+            b'\n' => "\\n".to_string(),
+            b'\r' => "\\r".to_string(),
+            b'\t' => "\\t".to_string(),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect();
+
+    let single_line = format!("b\"{}\"", pieces.concat());
+    if bytes.is_empty() || line_width == 0 || single_line.len() <= line_width {
+        return single_line;
+    }
+    // `b"` and `"` take three characters of every line
+    let lines = wrap(
+        &pieces,
+        line_width.saturating_sub(INDENT.len() + 3),
+        |line| format!("b\"{}\"", line.concat()),
+    );
+    format!("(\n{}\n)", indent(&lines))
+}
+
+/// Greedily pack items into lines no longer than `width` as measured by `render`,
+/// always placing at least one item on each line
+fn wrap(items: &[String], width: usize, render: impl Fn(&[String]) -> String) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < items.len() {
+        let mut end = start + 1;
+        while end < items.len() && render(&items[start..=end]).len() <= width {
+            end += 1;
+        }
+        lines.push(render(&items[start..end]));
+        start = end;
+    }
+    lines
+}
+
+fn indent(lines: &[String]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("{}{}", INDENT, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex_input::parse_hex_input;
+
+    const HELLO: [u8; 8] = [0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b];
+
+    #[test]
+    fn test_render_single_line() {
+        assert_eq!(
+            render_literal(&[0x81, 0x0a], LiteralLanguage::RustSlice, 0),
+            "&[0x81, 0x0a]"
+        );
+        assert_eq!(
+            render_literal(&[0x81, 0x0a], LiteralLanguage::RustVec, 80),
+            "vec![0x81, 0x0a]"
+        );
+        assert_eq!(
+            render_literal(&[0x81, 0x0a], LiteralLanguage::C, 80),
+            "const uint8_t data[2] = {0x81, 0x0a};"
+        );
+        assert_eq!(
+            render_literal(&[0x81, 0x0a], LiteralLanguage::Go, 80),
+            "[]byte{0x81, 0x0a}"
+        );
+        assert_eq!(
+            render_literal(&[0x81, 0x0a], LiteralLanguage::JavaScript, 80),
+            "new Uint8Array([0x81, 0x0a])"
+        );
+        assert_eq!(
+            render_literal(&HELLO, LiteralLanguage::Python, 80),
+            r#"b"\x81\xa5hello{""#
+        );
+        assert_eq!(
+            render_literal(b"\"\\\n", LiteralLanguage::Python, 80),
+            r#"b"\"\\\n""#
+        );
+    }
+
+    #[test]
+    fn test_render_wrapped() {
+        assert_eq!(
+            render_literal(&HELLO, LiteralLanguage::Go, 30),
+            "[]byte{\n    0x81, 0xa5, 0x68, 0x65,\n    0x6c, 0x6c, 0x6f, 0x7b,\n}"
+        );
+        assert_eq!(
+            render_literal(&HELLO, LiteralLanguage::Python, 16),
+            "(\n    b\"\\x81\"\n    b\"\\xa5he\"\n    b\"llo{\"\n)"
+        );
+    }
+
+    #[test]
+    fn test_literals_parse_back() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for language in LiteralLanguage::ALL {
+            for width in [0, 40, 100] {
+                let literal = render_literal(&bytes, language, width);
+                let (parsed, _) = parse_hex_input(&literal)
+                    .unwrap_or_else(|e| panic!("{:?} at {}: {}", language, width, e));
+                assert_eq!(parsed, bytes, "{:?} at {}", language, width);
+            }
+            // Empty payloads never wrap, even when the literal is wider than the line
+            let literal = render_literal(&[], language, 8);
+            assert!(!literal.contains('\n'), "{:?}: {}", language, literal);
+            let (parsed, _) =
+                parse_hex_input(&literal).unwrap_or_else(|e| panic!("empty {:?}: {}", language, e));
+            assert!(parsed.is_empty(), "empty {:?}", language);
+        }
+    }
+}
@@ -1,3 +1,12 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use rmp::Marker;
+
+use crate::error::{DecodeError, DecodeErrorKind, LimitKind};
+use crate::format;
+use crate::tagged;
+
 /// Represents a mapping between a JSON text range and its corresponding HEX byte range.
 #[derive(Debug, Clone)]
 pub struct PositionMapping {
@@ -7,8 +16,7 @@ pub struct PositionMapping {
     /// HEX byte position (byte indices in the msgpack data)
     pub hex_start: usize,
     pub hex_end: usize,
-    /// Type of the mapped element (used for debugging and potential future features)
-    #[allow(dead_code)]
+    /// Type of the mapped element
     pub mapping_type: MappingType,
 }
 
@@ -19,33 +27,250 @@ pub struct PositionMapping {
 pub enum MappingType {
     /// A key in a JSON object
     Key,
+    /// An earlier occurrence of a map key that a JSON object shows only once
+    DuplicateKey,
     /// A value (string, number, boolean, null, array, or object)
     Value,
     /// A container element (array or object brackets)
     Container,
 }
 
-/// Safely extract a fixed-size array from a slice with bounds checking.
-/// Returns an error if there aren't enough bytes.
-fn safe_array<const N: usize>(data: &[u8], start: usize) -> Result<[u8; N], String> {
-    if start + N > data.len() {
-        return Err(format!(
-            "Truncated data: expected {} bytes at position {}, but only {} bytes available",
-            N,
+/// How a buffer of back-to-back msgpack values is presented as JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StreamFormat {
+    /// A single msgpack value is a single JSON document
+    #[default]
+    Off,
+    /// Every top-level value becomes an element of one JSON array
+    JsonArray,
+    /// Every top-level value becomes one line of newline-delimited JSON
+    Ndjson,
+}
+
+/// Byte range `(start, end)` in the msgpack data
+pub type ByteRange = (usize, usize);
+
+/// Irregularities found while decoding that did not stop it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeReport {
+    /// Byte ranges resync mode skipped over, in order. Each starts where an element
+    /// failed and ends at the offset decoding resumed at.
+    pub skipped: Vec<ByteRange>,
+    /// Map keys that repeat an earlier key of the same map
+    pub duplicate_keys: Vec<DuplicateKey>,
+    /// In lint mode, headers wider than their value needs, innermost values first
+    pub wide_headers: Vec<WideHeader>,
+    /// In partial mode, byte ranges of the values the data ran out in, from their header
+    /// to the end of the data
+    pub truncated: Vec<ByteRange>,
+    /// Offsets where no value decodes without skipping bytes, so resync never tries them twice
+    failed: HashSet<usize>,
+}
+
+impl DecodeReport {
+    fn mark(&self) -> (usize, usize, usize, usize) {
+        (
+            self.skipped.len(),
+            self.duplicate_keys.len(),
+            self.wide_headers.len(),
+            self.truncated.len(),
+        )
+    }
+
+    /// Forget what was recorded since `mark` was taken
+    fn truncate(
+        &mut self,
+        (skipped, duplicate_keys, wide_headers, truncated): (usize, usize, usize, usize),
+    ) {
+        self.skipped.truncate(skipped);
+        self.duplicate_keys.truncate(duplicate_keys);
+        self.wide_headers.truncate(wide_headers);
+        self.truncated.truncate(truncated);
+    }
+
+    fn append(&mut self, other: DecodeReport) {
+        self.skipped.extend(other.skipped);
+        self.duplicate_keys.extend(other.duplicate_keys);
+        self.wide_headers.extend(other.wide_headers);
+        self.truncated.extend(other.truncated);
+        self.failed.extend(other.failed);
+    }
+
+    /// Remember that the value at `pos` failed with `e`. Limits depend on how deep the
+    /// value sits, so a value that exceeds one may still decode at another depth.
+    fn fail(&mut self, pos: usize, e: &DecodeError) {
+        if !matches!(e.kind, DecodeErrorKind::LimitExceeded { .. }) {
+            self.failed.insert(pos);
+        }
+    }
+}
+
+/// A map key that repeats an earlier key of the same map
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateKey {
+    /// Byte range of the key
+    pub range: ByteRange,
+    /// The key as JSON text, e.g. `"id"` or `1`
+    pub key: String,
+    /// Whether the map is shown as `$map` pairs, which keep every entry, rather than as an
+    /// object, which keeps only the last value
+    pub kept: bool,
+}
+
+/// A value stored with a wider format than the smallest one that holds it,
+/// e.g. `uint64` for 1 or `map16` for three entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct WideHeader {
+    /// Byte range of the header (the whole value for integers)
+    pub range: ByteRange,
+    /// The format the value is stored in
+    pub format: Marker,
+    /// The format the default encoder would choose
+    pub smallest: Marker,
+    /// The integer, or the length the header declares
+    pub value: i128,
+}
+
+impl WideHeader {
+    /// Bytes saved by re-encoding with the smallest format
+    pub fn saved(&self) -> usize {
+        format::header_len(self.format) - format::header_len(self.smallest)
+    }
+}
+
+impl std::fmt::Display for WideHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (start, _) = self.range;
+        let what = match format::family(self.format) {
+            format::Family::Int => "holds",
+            _ => "declares length",
+        };
+        let saved = self.saved();
+        write!(
+            f,
+            "{} at offset {} (0x{:x}) {} {}; {} would save {} byte{}",
+            format::format_name(self.format),
+            start,
             start,
-            data.len().saturating_sub(start)
+            what,
+            self.value,
+            format::format_name(self.smallest),
+            saved,
+            if saved == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Options controlling how msgpack data is rendered as JSON.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeOptions {
+    /// Render maps with non-string keys as `{"$map": [[key, value], ...]}`
+    /// instead of stringifying their keys
+    pub preserve_map_keys: bool,
+    /// Annotate values whose msgpack format differs from what the default encoder
+    /// would choose (e.g. `{"$uint16": 1}`) so they re-encode byte-identically
+    pub wire_exact: bool,
+    /// Decode every concatenated top-level value instead of only the first
+    pub stream: StreamFormat,
+    /// Bounds on nesting, container sizes and input size
+    pub limits: Limits,
+    /// Decode truncated data as far as it goes instead of failing, leaving a
+    /// `{"$truncated": ...}` marker where it runs out
+    pub partial: bool,
+    /// Render maps that repeat a key as `{"$map": [[key, value], ...]}` so every entry
+    /// is kept, instead of letting later entries overwrite earlier ones
+    pub keep_duplicate_keys: bool,
+    /// Skip over corrupt bytes (unknown format bytes, impossible lengths) and resume
+    /// at the next offset where a value decodes
+    pub resync: bool,
+    /// Report every header wider than its value needs in `DecodeReport::wide_headers`
+    pub lint: bool,
+}
+
+/// Bounds on untrusted input, so a small crafted payload is rejected with an error
+/// instead of exhausting the stack or memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum number of nested arrays and maps
+    pub max_depth: usize,
+    /// Maximum number of elements (or map entries) a single array or map may declare
+    pub max_elements: usize,
+    /// Maximum size in bytes of the msgpack data, and of JSON text being encoded
+    pub max_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            // Far deeper than real documents, yet well within the 1 MiB wasm stack
+            max_depth: 64,
+            max_elements: 1_000_000,
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Check the header of a container at `pos` that declares `count` elements of at least
+/// `min_bytes` each after a `header_len` byte header. `depth` is the container's nesting level.
+/// In partial mode the count is not checked against the data, so the elements that are
+/// there still decode before the data runs out.
+fn check_container(
+    data: &[u8],
+    pos: usize,
+    header_len: usize,
+    count: usize,
+    min_bytes: usize,
+    depth: usize,
+    options: &DecodeOptions,
+) -> Result<(), DecodeError> {
+    let limits = &options.limits;
+    if depth > limits.max_depth {
+        return Err(DecodeError::limit_exceeded(
+            data,
+            pos,
+            LimitKind::Depth,
+            limits.max_depth,
+            depth,
+        ));
+    }
+    if count > limits.max_elements {
+        return Err(DecodeError::limit_exceeded(
+            data,
+            pos,
+            LimitKind::Elements,
+            limits.max_elements,
+            count,
         ));
     }
-    data[start..start + N]
-        .try_into()
-        .map_err(|_| "Failed to convert slice to array".to_string())
+    if options.partial {
+        return Ok(());
+    }
+    // Don't trust the declared count further than the data that is actually there
+    let needed = count.saturating_mul(min_bytes).saturating_add(header_len);
+    if pos.saturating_add(needed) > data.len() {
+        return Err(DecodeError::truncated(data, pos, needed));
+    }
+    Ok(())
+}
+
+/// Safely extract a fixed-size array at `skip` bytes into the value starting at `pos`.
+/// Returns an error located at the value if there aren't enough bytes.
+fn safe_array<const N: usize>(
+    data: &[u8],
+    pos: usize,
+    skip: usize,
+) -> Result<[u8; N], DecodeError> {
+    let start = pos + skip;
+    data.get(start..start + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| DecodeError::truncated(data, pos, skip + N))
 }
 
-/// Safely get a byte at a position with bounds checking.
-fn safe_byte(data: &[u8], pos: usize) -> Result<u8, String> {
-    data.get(pos)
+/// Safely get the byte `skip` bytes into the value starting at `pos`.
+fn safe_byte(data: &[u8], pos: usize, skip: usize) -> Result<u8, DecodeError> {
+    data.get(pos + skip)
         .copied()
-        .ok_or_else(|| format!("Unexpected end of data at position {}", pos))
+        .ok_or_else(|| DecodeError::truncated(data, pos, skip + 1))
 }
 
 /// Skip whitespace characters in a JSON string starting from a given position.
@@ -68,11 +293,563 @@ fn skip_whitespace_and_delimiters(json_string: &str, pos: usize, delimiters: &st
     p
 }
 
+/// Find the end of the JSON value starting at a given position, honouring nesting and strings.
+fn skip_json_value(json_string: &str, pos: usize) -> usize {
+    let chars: Vec<char> = json_string.chars().collect();
+    let mut p = pos;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    while p < chars.len() {
+        let c = chars[p];
+        if in_string {
+            match c {
+                '\\' => p += 1,
+                '"' => {
+                    in_string = false;
+                    if depth == 0 {
+                        return p + 1;
+                    }
+                }
+                _ => {}
+            }
+        } else {
+            match c {
+                '"' => in_string = true,
+                '{' | '[' => depth += 1,
+                '}' | ']' => {
+                    if depth == 0 {
+                        return p;
+                    }
+                    depth -= 1;
+                    if depth == 0 {
+                        return p + 1;
+                    }
+                }
+                ',' if depth == 0 => return p,
+                c if c.is_whitespace() && depth == 0 => return p,
+                _ => {}
+            }
+        }
+        p += 1;
+    }
+    p
+}
+
+/// End of the value at `pos` made of a `header_len` byte header and `length` bytes of
+/// payload. `length` comes from the input, so the sum must not wrap where `usize` is 32 bits.
+fn payload_end(
+    data: &[u8],
+    pos: usize,
+    header_len: usize,
+    length: usize,
+) -> Result<usize, DecodeError> {
+    let size = header_len.saturating_add(length);
+    match pos.checked_add(size) {
+        Some(end) if end <= data.len() => Ok(end),
+        _ => Err(DecodeError::truncated(data, pos, size)),
+    }
+}
+
+/// Parse the header of an ext value starting at `pos`.
+/// Returns the extension type, the data start position and the data length.
+fn parse_ext_header(data: &[u8], pos: usize) -> Result<(i8, usize, usize), DecodeError> {
+    let byte = safe_byte(data, pos, 0)?;
+    let (length, header_len) = match byte {
+        // fixext 1/2/4/8/16
+        0xd4..=0xd8 => (1usize << (byte - 0xd4), 1),
+        // ext 8
+        0xc7 => (safe_byte(data, pos, 1)? as usize, 2),
+        // ext 16
+        0xc8 => {
+            let len_bytes: [u8; 2] = safe_array(data, pos, 1)?;
+            (u16::from_be_bytes(len_bytes) as usize, 3)
+        }
+        // ext 32
+        0xc9 => {
+            let len_bytes: [u8; 4] = safe_array(data, pos, 1)?;
+            (u32::from_be_bytes(len_bytes) as usize, 5)
+        }
+        _ => return Err(DecodeError::invalid_format(data, pos)),
+    };
+    let ext_type = safe_byte(data, pos, header_len)? as i8;
+    let end = payload_end(data, pos, header_len + 1, length)?;
+    Ok((ext_type, end - length, length))
+}
+
+/// In partial mode, fold what the container at `pos` decoded before the data ran out into
+/// the truncation error. `wrap` receives the failing child, or a `$truncated` marker standing
+/// in for it, and returns the container. Other errors pass through unchanged.
+fn keep_partial(
+    mut e: DecodeError,
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+    report: &mut DecodeReport,
+    wrap: impl FnOnce(serde_json::Value) -> serde_json::Value,
+) -> DecodeError {
+    if let (
+        true,
+        DecodeErrorKind::Truncated {
+            expected,
+            available,
+        },
+    ) = (options.partial, &e.kind)
+    {
+        let child = match e.partial.take() {
+            Some(child) => *child,
+            None => {
+                // The innermost value the data ran out in is the child, or the container
+                // if the data ends where the child would start
+                let start = if e.offset < data.len() { e.offset } else { pos };
+                if start < data.len() {
+                    report.truncated.push((start, data.len()));
+                }
+                tagged::truncated_marker(e.offset, *expected, *available)
+            }
+        };
+        e.partial = Some(Box::new(wrap(child)));
+    }
+    e
+}
+
+/// The entries of a map body as far as they decode
+#[derive(Default)]
+struct MapEntries {
+    entries: Vec<(serde_json::Value, serde_json::Value)>,
+    /// Byte range of each key, starting where decoding resumed if the key was resynced
+    key_ranges: Vec<ByteRange>,
+    /// The key of the entry whose value failed to decode, if that is where the body stopped
+    failed_key: Option<serde_json::Value>,
+}
+
+/// Decode `count` consecutive key-value pairs starting at `pos`, nested `depth` levels deep,
+/// into `map`. Returns the end position.
+fn decode_map_entries(
+    data: &[u8],
+    pos: usize,
+    count: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+    map: &mut MapEntries,
+) -> Result<usize, DecodeError> {
+    let mut current_pos = pos;
+    for index in 0..count {
+        let after = (count - 1 - index) * 2;
+        let bound = resume_bound(data, after + 1, options);
+        let skipped = report.skipped.len();
+        let (key, key_end) = parse_element(data, current_pos, bound, options, depth, report)?;
+        let key_start = match report.skipped.get(skipped) {
+            Some(&(start, resume)) if start == current_pos => resume,
+            _ => current_pos,
+        };
+        map.key_ranges.push((key_start, key_end));
+        let bound = resume_bound(data, after, options);
+        let (value, value_end) = match parse_element(data, key_end, bound, options, depth, report) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                map.failed_key = Some(key);
+                return Err(e);
+            }
+        };
+        map.entries.push((key, value));
+        current_pos = value_end;
+    }
+    Ok(current_pos)
+}
+
+/// Parse the `count` key-value pairs after the `header_len` byte header of the map at `pos`,
+/// nested `depth` levels deep. Returns the decoded entries and the end position.
+fn parse_map_entries(
+    data: &[u8],
+    pos: usize,
+    header_len: usize,
+    count: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+) -> Result<(Vec<(serde_json::Value, serde_json::Value)>, usize), DecodeError> {
+    let mut map = MapEntries::default();
+    let body = pos + header_len;
+    let result = decode_map_entries(data, body, count, options, depth, report, &mut map);
+    let MapEntries {
+        mut entries,
+        key_ranges,
+        failed_key,
+    } = map;
+    let end = match (result, failed_key) {
+        (Ok(end), _) => end,
+        (Err(e), None) => {
+            // A key the data ran out in has no value, so it ends the map as a pair of its
+            // own, which only the `$map` form can hold
+            return Err(keep_partial(e, data, pos, options, report, |key| {
+                let mut map = tagged::map_pairs_to_json(entries);
+                if let Some(serde_json::Value::Array(pairs)) = map.get_mut(tagged::MAP_TAG) {
+                    pairs.push(serde_json::Value::Array(vec![key]));
+                }
+                map
+            }));
+        }
+        (Err(e), Some(key)) => {
+            let e = e.within_key(&key_to_string(&key));
+            return Err(keep_partial(e, data, pos, options, report, |value| {
+                entries.push((key, value));
+                entries_to_json(entries, options)
+            }));
+        }
+    };
+
+    // Pairs keep keys such as `1` and `"1"` apart, while an object merges them
+    let kept = renders_as_pairs(&entries, options);
+    let mut seen_keys = HashSet::new();
+    for ((key, _), &range) in entries.iter().zip(&key_ranges) {
+        let identity = if kept {
+            key.to_string()
+        } else {
+            key_to_string(key)
+        };
+        if !seen_keys.insert(identity) {
+            report.duplicate_keys.push(DuplicateKey {
+                range,
+                key: key.to_string(),
+                kept,
+            });
+        }
+    }
+    Ok((entries, end))
+}
+
+/// Whether map entries are rendered as `{"$map": [[key, value], ...]}` rather than a JSON object
+fn renders_as_pairs(
+    entries: &[(serde_json::Value, serde_json::Value)],
+    options: &DecodeOptions,
+) -> bool {
+    // Keys that are not valid UTF-8, and a lone key that reads as a tag such as `$bin`,
+    // always need pairs to re-encode exactly
+    let is_lone_tag =
+        matches!(entries, [(serde_json::Value::String(key), _)] if tagged::is_tag_name(key));
+    let is_raw_str =
+        |key: &serde_json::Value| matches!(tagged::as_tagged(key), Some((tagged::RAW_STR_TAG, _)));
+    is_lone_tag
+        || entries.iter().any(|(key, _)| is_raw_str(key))
+        || ((options.preserve_map_keys || options.wire_exact)
+            && entries.iter().any(|(key, _)| !key.is_string()))
+        || (options.keep_duplicate_keys && has_duplicate_keys(entries))
+}
+
+/// Whether two entries would end up under the same JSON object key
+fn has_duplicate_keys(entries: &[(serde_json::Value, serde_json::Value)]) -> bool {
+    let mut seen_keys = HashSet::new();
+    !entries
+        .iter()
+        .all(|(key, _)| seen_keys.insert(key_to_string(key)))
+}
+
+/// Render a non-string map key as the JSON object key it is stringified to
+fn key_to_string(key: &serde_json::Value) -> String {
+    match key {
+        serde_json::Value::String(s) => s.clone(),
+        _ => key.to_string(),
+    }
+}
+
+/// Parse the map at `pos`, whose `header_len` byte header declares `count` entries,
+/// nested `depth` levels deep.
+fn parse_map(
+    data: &[u8],
+    pos: usize,
+    header_len: usize,
+    count: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+) -> Result<(serde_json::Value, usize), DecodeError> {
+    let (entries, end) = parse_map_entries(data, pos, header_len, count, options, depth, report)?;
+    Ok((entries_to_json(entries, options), end))
+}
+
+/// Render decoded map entries as a JSON object, or as `$map` pairs when required
+fn entries_to_json(
+    entries: Vec<(serde_json::Value, serde_json::Value)>,
+    options: &DecodeOptions,
+) -> serde_json::Value {
+    if renders_as_pairs(&entries, options) {
+        return tagged::map_pairs_to_json(entries);
+    }
+    let mut obj = serde_json::Map::new();
+    for (key, value) in entries {
+        obj.insert(key_to_string(&key), value);
+    }
+    serde_json::Value::Object(obj)
+}
+
+/// The format of the value at `pos`, the format the default encoder would choose for it,
+/// and the integer or declared length that choice depends on (0 for other values)
+fn smallest_format(data: &[u8], pos: usize) -> Result<(Marker, Marker, i128), DecodeError> {
+    let marker = Marker::from_u8(safe_byte(data, pos, 0)?);
+    // Reading the header only fails when the data ends inside it
+    let truncated = |_| DecodeError::truncated(data, pos, data.len() - pos + 1);
+    Ok(match format::family(marker) {
+        format::Family::Int => {
+            let value = format::read_int(data, pos).map_err(truncated)?;
+            (marker, format::smallest_int_marker(value), value)
+        }
+        // float32 always carries its own `$float32` tag
+        format::Family::Float => (marker, marker, 0),
+        _ => match format::read_length_header(data, pos).map_err(truncated)? {
+            Some((len, _)) => (
+                marker,
+                format::smallest_length_marker(marker, len),
+                len as i128,
+            ),
+            None => (marker, marker, 0),
+        },
+    })
+}
+
+/// In wire-exact mode, the format a value must be annotated with because the default
+/// encoder would choose a different one. Returns `None` for canonically encoded values.
+fn wire_annotation(data: &[u8], pos: usize) -> Result<Option<Marker>, DecodeError> {
+    let (marker, smallest, _) = smallest_format(data, pos)?;
+    Ok((smallest != marker).then_some(marker))
+}
+
+/// In lint mode, the header of the value at `pos` if a smaller format would hold it.
+/// Formats that differ only in signedness, such as `int32` for 70000, take no more room.
+fn wide_header(data: &[u8], pos: usize) -> Result<Option<WideHeader>, DecodeError> {
+    let (format, smallest, value) = smallest_format(data, pos)?;
+    let header = WideHeader {
+        range: (pos, pos + format::header_len(format)),
+        format,
+        smallest,
+        value,
+    };
+    Ok((format::header_len(format) > format::header_len(smallest)).then_some(header))
+}
+
 /// Parse a msgpack value and track the byte range it occupies.
 /// Returns the decoded value and the end position.
-fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, usize), String> {
+pub fn parse_msgpack_value(
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+) -> Result<(serde_json::Value, usize), DecodeError> {
+    parse_msgpack_value_with_report(data, pos, options).map(|(value, end, _)| (value, end))
+}
+
+/// Like `parse_msgpack_value`, also returning what was skipped or lost along the way.
+pub fn parse_msgpack_value_with_report(
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+) -> Result<(serde_json::Value, usize, DecodeReport), DecodeError> {
+    let max_size = options.limits.max_size;
+    if data.len() > max_size {
+        return Err(DecodeError::limit_exceeded(
+            data,
+            max_size,
+            LimitKind::Size,
+            max_size,
+            data.len(),
+        ));
+    }
+    let mut report = DecodeReport::default();
+    match parse_element(data, pos, data.len(), options, 0, &mut report) {
+        Ok((value, end)) => Ok((value, end, report)),
+        Err(e) if options.partial => {
+            let mut e = keep_partial(e, data, pos, options, &mut report, |value| value);
+            match e.partial.take() {
+                Some(value) => Ok((*value, data.len(), report)),
+                None => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// One token of a disassembly listing: the header of a value, and the value itself
+/// unless it is an array or map, whose elements follow as tokens of their own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// Byte range of the header (the whole value for nil, bool, int and float)
+    pub header: ByteRange,
+    /// The format named by the first header byte
+    pub format: Marker,
+    /// Length declared by str, bin and ext headers, or the element count of arrays
+    /// and entry count of maps
+    pub length: Option<usize>,
+    /// The decoded value of anything but arrays and maps
+    pub value: Option<serde_json::Value>,
+    /// Number of arrays and maps the value is nested in
+    pub depth: usize,
+}
+
+/// List the tokens of every value in `data` in byte order, stopping at the first one
+/// that fails to decode. Returns the tokens up to there and the error, if any.
+pub fn disassemble(data: &[u8], options: &DecodeOptions) -> (Vec<Token>, Option<DecodeError>) {
+    let mut tokens = Vec::new();
+    let max_size = options.limits.max_size;
+    if data.len() > max_size {
+        let e = DecodeError::limit_exceeded(data, max_size, LimitKind::Size, max_size, data.len());
+        return (tokens, Some(e));
+    }
+    let mut pos = 0;
+    while pos < data.len() {
+        match disassemble_value(data, pos, options, 0, &mut tokens) {
+            Ok(end) => pos = end,
+            Err(e) => return (tokens, Some(e)),
+        }
+    }
+    (tokens, None)
+}
+
+/// Append the tokens of the value at `pos`, nested `depth` levels deep, and return its end
+fn disassemble_value(
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    tokens: &mut Vec<Token>,
+) -> Result<usize, DecodeError> {
+    let format = Marker::from_u8(safe_byte(data, pos, 0)?);
+    let header_len = format::header_len(format);
+    let min_bytes = match format::family(format) {
+        format::Family::Array => 1,
+        format::Family::Map => 2,
+        _ => {
+            let (value, end) =
+                decode_value(data, pos, options, depth, &mut DecodeReport::default())?;
+            tokens.push(Token {
+                header: (pos, pos + header_len),
+                format,
+                length: format::read_length_header(data, pos)
+                    .ok()
+                    .flatten()
+                    .map(|(len, _)| len),
+                value: Some(value),
+                depth,
+            });
+            return Ok(end);
+        }
+    };
+    let count = match format::read_length_header(data, pos) {
+        Ok(Some((count, _))) => count,
+        _ => return Err(DecodeError::truncated(data, pos, header_len)),
+    };
+    check_container(data, pos, header_len, count, min_bytes, depth + 1, options)?;
+    tokens.push(Token {
+        header: (pos, pos + header_len),
+        format,
+        length: Some(count),
+        value: None,
+        depth,
+    });
+    let mut end = pos + header_len;
+    for _ in 0..count * min_bytes {
+        end = disassemble_value(data, end, options, depth + 1, tokens)?;
+    }
+    Ok(end)
+}
+
+/// Whether resync mode skips over the bytes behind this error: unknown format bytes, and
+/// lengths running past the end of the data unless partial mode keeps those as truncation
+fn is_corruption(e: &DecodeError, options: &DecodeOptions) -> bool {
+    match e.kind {
+        DecodeErrorKind::InvalidFormat => true,
+        DecodeErrorKind::Truncated { .. } => e.format_byte.is_some() && !options.partial,
+        DecodeErrorKind::LimitExceeded { .. } => false,
+    }
+}
+
+/// End of the bytes an element may resume within when the elements after it in its
+/// container need at least `after` more bytes. Resuming any later leaves the container
+/// short of data, except in partial mode where running out is expected.
+fn resume_bound(data: &[u8], after: usize, options: &DecodeOptions) -> usize {
+    if options.partial {
+        data.len()
+    } else {
+        data.len().saturating_sub(after)
+    }
+}
+
+/// Parse the value at `pos` as an element of a container or stream. In resync mode a value
+/// that fails to decode is skipped a byte at a time until one decodes before `bound`, and
+/// the skipped range is recorded.
+fn parse_element(
+    data: &[u8],
+    pos: usize,
+    bound: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+) -> Result<(serde_json::Value, usize), DecodeError> {
+    let recorded = report.mark();
+    let error = match parse_value(data, pos, options, depth, report) {
+        Err(e) if options.resync && is_corruption(&e, options) => e,
+        Err(e) => {
+            report.fail(pos, &e);
+            return Err(e);
+        }
+        result => return result,
+    };
+    report.truncate(recorded);
+    report.fail(pos, &error);
+    // The values tried here don't resync inside themselves: bytes they would skip are
+    // tried as resume offsets by this scan anyway
+    let strict = DecodeOptions {
+        resync: false,
+        ..options.clone()
+    };
+    for resume in pos + 1..bound {
+        if report.failed.contains(&resume) {
+            continue;
+        }
+        let mut nested = DecodeReport::default();
+        match parse_value(data, resume, &strict, depth, &mut nested) {
+            Ok((value, end)) => {
+                report.skipped.push((pos, resume));
+                report.append(nested);
+                return Ok((value, end));
+            }
+            Err(e) => {
+                report.failed.extend(nested.failed);
+                report.fail(resume, &e);
+            }
+        }
+    }
+    Err(error)
+}
+
+/// Parse the value at `pos`, which sits inside `depth` arrays and maps.
+fn parse_value(
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+) -> Result<(serde_json::Value, usize), DecodeError> {
+    let (value, end) = decode_value(data, pos, options, depth, report)?;
+    if options.lint {
+        report.wide_headers.extend(wide_header(data, pos)?);
+    }
+    if options.wire_exact {
+        if let Some(marker) = wire_annotation(data, pos)? {
+            return Ok((tagged::annotate_format(marker, value), end));
+        }
+    }
+    Ok((value, end))
+}
+
+/// Decode the value at `pos` according to its format byte, without wire-format annotation.
+fn decode_value(
+    data: &[u8],
+    pos: usize,
+    options: &DecodeOptions,
+    depth: usize,
+    report: &mut DecodeReport,
+) -> Result<(serde_json::Value, usize), DecodeError> {
     if pos >= data.len() {
-        return Err("Unexpected end of data".to_string());
+        return Err(DecodeError::truncated(data, pos, 1));
     }
 
     let byte = data[pos];
@@ -91,29 +868,29 @@ fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, us
     // fixmap (0x80 - 0x8f)
     if (0x80..=0x8f).contains(&byte) {
         let count = (byte & 0x0f) as usize;
-        let mut current_pos = pos + 1;
-        let mut obj = serde_json::Map::new();
-        for _ in 0..count {
-            let (key, key_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = key_end;
-            let (value, value_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = value_end;
-            let key_str = match key {
-                serde_json::Value::String(s) => s,
-                _ => key.to_string(),
-            };
-            obj.insert(key_str, value);
-        }
-        return Ok((serde_json::Value::Object(obj), current_pos));
+        check_container(data, pos, 1, count, 2, depth + 1, options)?;
+        return parse_map(data, pos, 1, count, options, depth + 1, report);
     }
 
     // fixarray (0x90 - 0x9f)
     if (0x90..=0x9f).contains(&byte) {
         let count = (byte & 0x0f) as usize;
+        check_container(data, pos, 1, count, 1, depth + 1, options)?;
         let mut current_pos = pos + 1;
         let mut arr = Vec::new();
-        for _ in 0..count {
-            let (value, end) = parse_msgpack_value(data, current_pos)?;
+        for index in 0..count {
+            let bound = resume_bound(data, count - 1 - index, options);
+            let (value, end) =
+                match parse_element(data, current_pos, bound, options, depth + 1, report) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        let e = e.within_index(index);
+                        return Err(keep_partial(e, data, pos, options, report, |value| {
+                            arr.push(value);
+                            serde_json::Value::Array(arr)
+                        }));
+                    }
+                };
             arr.push(value);
             current_pos = end;
         }
@@ -123,9 +900,9 @@ fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, us
     // fixstr (0xa0 - 0xbf)
     if (0xa0..=0xbf).contains(&byte) {
         let length = (byte & 0x1f) as usize;
-        let str_bytes = &data[pos + 1..pos + 1 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        return Ok((serde_json::Value::String(s), pos + 1 + length));
+        let end = payload_end(data, pos, 1, length)?;
+        let str_bytes = &data[end - length..end];
+        return Ok((tagged::str_to_json(str_bytes), end));
     }
 
     // nil
@@ -145,121 +922,132 @@ fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, us
 
     // float 32
     if byte == 0xca {
-        let bytes: [u8; 4] = safe_array(data, pos + 1)?;
-        let value = f32::from_be_bytes(bytes) as f64;
-        return Ok((
-            serde_json::Value::Number(serde_json::Number::from_f64(value).unwrap_or(0.into())),
-            pos + 5,
-        ));
+        let bytes: [u8; 4] = safe_array(data, pos, 1)?;
+        let value = f32::from_be_bytes(bytes);
+        let payload = if value.is_finite() {
+            // Widen via the shortest decimal that round-trips as f32, so 0.1f32 shows as 0.1
+            let widened: f64 = value.to_string().parse().unwrap_or_default();
+            serde_json::Number::from_f64(widened).map_or(serde_json::Value::Null, Into::into)
+        } else {
+            tagged::non_finite_to_json(Marker::F32, u32::from_be_bytes(bytes) as u64)
+        };
+        return Ok((tagged::annotate_format(Marker::F32, payload), pos + 5));
     }
 
     // float 64
     if byte == 0xcb {
-        let bytes: [u8; 8] = safe_array(data, pos + 1)?;
-        let value = f64::from_be_bytes(bytes);
-        return Ok((
-            serde_json::Value::Number(serde_json::Number::from_f64(value).unwrap_or(0.into())),
-            pos + 9,
-        ));
+        let bytes: [u8; 8] = safe_array(data, pos, 1)?;
+        // Negative zero is kept as the JSON number -0.0; NaN and infinities are tagged
+        let value = match serde_json::Number::from_f64(f64::from_be_bytes(bytes)) {
+            Some(number) => serde_json::Value::Number(number),
+            None => tagged::annotate_format(
+                Marker::F64,
+                tagged::non_finite_to_json(Marker::F64, u64::from_be_bytes(bytes)),
+            ),
+        };
+        return Ok((value, pos + 9));
     }
 
     // uint 8
     if byte == 0xcc {
-        let value = safe_byte(data, pos + 1)?;
+        let value = safe_byte(data, pos, 1)?;
         return Ok((serde_json::Value::Number(value.into()), pos + 2));
     }
 
     // uint 16
     if byte == 0xcd {
-        let bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let value = u16::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number(value.into()), pos + 3));
     }
 
     // uint 32
     if byte == 0xce {
-        let bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let value = u32::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number(value.into()), pos + 5));
     }
 
     // uint 64
     if byte == 0xcf {
-        let bytes: [u8; 8] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 8] = safe_array(data, pos, 1)?;
         let value = u64::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number(value.into()), pos + 9));
     }
 
     // int 8
     if byte == 0xd0 {
-        let value = safe_byte(data, pos + 1)? as i8;
+        let value = safe_byte(data, pos, 1)? as i8;
         return Ok((serde_json::Value::Number((value as i64).into()), pos + 2));
     }
 
     // int 16
     if byte == 0xd1 {
-        let bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let value = i16::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number((value as i64).into()), pos + 3));
     }
 
     // int 32
     if byte == 0xd2 {
-        let bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let value = i32::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number((value as i64).into()), pos + 5));
     }
 
     // int 64
     if byte == 0xd3 {
-        let bytes: [u8; 8] = safe_array(data, pos + 1)?;
+        let bytes: [u8; 8] = safe_array(data, pos, 1)?;
         let value = i64::from_be_bytes(bytes);
         return Ok((serde_json::Value::Number(value.into()), pos + 9));
     }
 
     // str 8
     if byte == 0xd9 {
-        let length = safe_byte(data, pos + 1)? as usize;
-        if pos + 2 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[pos + 2..pos + 2 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        return Ok((serde_json::Value::String(s), pos + 2 + length));
+        let length = safe_byte(data, pos, 1)? as usize;
+        let end = payload_end(data, pos, 2, length)?;
+        let str_bytes = &data[end - length..end];
+        return Ok((tagged::str_to_json(str_bytes), end));
     }
 
     // str 16
     if byte == 0xda {
-        let len_bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let length = u16::from_be_bytes(len_bytes) as usize;
-        if pos + 3 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[pos + 3..pos + 3 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        return Ok((serde_json::Value::String(s), pos + 3 + length));
+        let end = payload_end(data, pos, 3, length)?;
+        let str_bytes = &data[end - length..end];
+        return Ok((tagged::str_to_json(str_bytes), end));
     }
 
     // str 32
     if byte == 0xdb {
-        let len_bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let length = u32::from_be_bytes(len_bytes) as usize;
-        if pos + 5 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[pos + 5..pos + 5 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        return Ok((serde_json::Value::String(s), pos + 5 + length));
+        let end = payload_end(data, pos, 5, length)?;
+        let str_bytes = &data[end - length..end];
+        return Ok((tagged::str_to_json(str_bytes), end));
     }
 
     // array 16
     if byte == 0xdc {
-        let len_bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let count = u16::from_be_bytes(len_bytes) as usize;
+        check_container(data, pos, 3, count, 1, depth + 1, options)?;
         let mut current_pos = pos + 3;
         let mut arr = Vec::new();
-        for _ in 0..count {
-            let (value, end) = parse_msgpack_value(data, current_pos)?;
+        for index in 0..count {
+            let bound = resume_bound(data, count - 1 - index, options);
+            let (value, end) =
+                match parse_element(data, current_pos, bound, options, depth + 1, report) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        let e = e.within_index(index);
+                        return Err(keep_partial(e, data, pos, options, report, |value| {
+                            arr.push(value);
+                            serde_json::Value::Array(arr)
+                        }));
+                    }
+                };
             arr.push(value);
             current_pos = end;
         }
@@ -268,12 +1056,24 @@ fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, us
 
     // array 32
     if byte == 0xdd {
-        let len_bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let count = u32::from_be_bytes(len_bytes) as usize;
+        check_container(data, pos, 5, count, 1, depth + 1, options)?;
         let mut current_pos = pos + 5;
         let mut arr = Vec::new();
-        for _ in 0..count {
-            let (value, end) = parse_msgpack_value(data, current_pos)?;
+        for index in 0..count {
+            let bound = resume_bound(data, count - 1 - index, options);
+            let (value, end) =
+                match parse_element(data, current_pos, bound, options, depth + 1, report) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        let e = e.within_index(index);
+                        return Err(keep_partial(e, data, pos, options, report, |value| {
+                            arr.push(value);
+                            serde_json::Value::Array(arr)
+                        }));
+                    }
+                };
             arr.push(value);
             current_pos = end;
         }
@@ -282,89 +1082,123 @@ fn parse_msgpack_value(data: &[u8], pos: usize) -> Result<(serde_json::Value, us
 
     // map 16
     if byte == 0xde {
-        let len_bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let count = u16::from_be_bytes(len_bytes) as usize;
-        let mut current_pos = pos + 3;
-        let mut obj = serde_json::Map::new();
-        for _ in 0..count {
-            let (key, key_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = key_end;
-            let (value, value_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = value_end;
-            let key_str = match key {
-                serde_json::Value::String(s) => s,
-                _ => key.to_string(),
-            };
-            obj.insert(key_str, value);
-        }
-        return Ok((serde_json::Value::Object(obj), current_pos));
+        check_container(data, pos, 3, count, 2, depth + 1, options)?;
+        return parse_map(data, pos, 3, count, options, depth + 1, report);
     }
 
     // map 32
     if byte == 0xdf {
-        let len_bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let count = u32::from_be_bytes(len_bytes) as usize;
-        let mut current_pos = pos + 5;
-        let mut obj = serde_json::Map::new();
-        for _ in 0..count {
-            let (key, key_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = key_end;
-            let (value, value_end) = parse_msgpack_value(data, current_pos)?;
-            current_pos = value_end;
-            let key_str = match key {
-                serde_json::Value::String(s) => s,
-                _ => key.to_string(),
-            };
-            obj.insert(key_str, value);
-        }
-        return Ok((serde_json::Value::Object(obj), current_pos));
+        check_container(data, pos, 5, count, 2, depth + 1, options)?;
+        return parse_map(data, pos, 5, count, options, depth + 1, report);
     }
 
     // bin 8
     if byte == 0xc4 {
-        let length = safe_byte(data, pos + 1)? as usize;
-        if pos + 2 + length > data.len() {
-            return Err("Truncated binary data".to_string());
-        }
-        return Ok((serde_json::Value::Null, pos + 2 + length));
+        let length = safe_byte(data, pos, 1)? as usize;
+        let end = payload_end(data, pos, 2, length)?;
+        let value = tagged::bin_to_json(&data[end - length..end]);
+        return Ok((value, end));
     }
 
     // bin 16
     if byte == 0xc5 {
-        let len_bytes: [u8; 2] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, pos, 1)?;
         let length = u16::from_be_bytes(len_bytes) as usize;
-        if pos + 3 + length > data.len() {
-            return Err("Truncated binary data".to_string());
-        }
-        return Ok((serde_json::Value::Null, pos + 3 + length));
+        let end = payload_end(data, pos, 3, length)?;
+        let value = tagged::bin_to_json(&data[end - length..end]);
+        return Ok((value, end));
     }
 
     // bin 32
     if byte == 0xc6 {
-        let len_bytes: [u8; 4] = safe_array(data, pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, pos, 1)?;
         let length = u32::from_be_bytes(len_bytes) as usize;
-        if pos + 5 + length > data.len() {
-            return Err("Truncated binary data".to_string());
-        }
-        return Ok((serde_json::Value::Null, pos + 5 + length));
+        let end = payload_end(data, pos, 5, length)?;
+        let value = tagged::bin_to_json(&data[end - length..end]);
+        return Ok((value, end));
+    }
+
+    // fixext 1/2/4/8/16 and ext 8/16/32
+    if (0xd4..=0xd8).contains(&byte) || (0xc7..=0xc9).contains(&byte) {
+        let (ext_type, data_start, length) = parse_ext_header(data, pos)?;
+        let ext_data = &data[data_start..data_start + length];
+        let value = if ext_type == tagged::TIMESTAMP_EXT_TYPE {
+            tagged::timestamp_to_json(ext_data, options.wire_exact)
+        } else {
+            tagged::ext_to_json(ext_type, ext_data)
+        };
+        return Ok((value, data_start + length));
     }
 
-    Err(format!(
-        "Unknown msgpack format byte: 0x{:02x} at position {}",
-        byte, pos
-    ))
+    Err(DecodeError::invalid_format(data, pos))
 }
 
-/// Build position mappings between msgpack bytes and JSON text positions.
+/// Build position mappings between msgpack bytes and JSON text positions. `resumed` maps
+/// the start of each element resync mode skipped to the offset decoding resumed at.
 fn build_mappings(
     data: &[u8],
     json_string: &str,
     mappings: &mut Vec<PositionMapping>,
     hex_pos: usize,
     json_pos: usize,
-) -> Result<(usize, usize), String> {
+    options: &DecodeOptions,
+    resumed: &HashMap<usize, usize>,
+) -> Result<(usize, usize), DecodeError> {
+    let hex_pos = resumed.get(&hex_pos).copied().unwrap_or(hex_pos);
+    if options.wire_exact && wire_annotation(data, hex_pos)?.is_some() {
+        // Step into the `{"$<format>": <value>}` wrapper and map the inner value
+        let json_chars: Vec<char> = json_string.chars().collect();
+        let mut current_json_pos = skip_whitespace(json_string, json_pos);
+        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '{' {
+            current_json_pos += 1;
+        }
+        current_json_pos = skip_whitespace(json_string, current_json_pos);
+        current_json_pos = skip_json_value(json_string, current_json_pos);
+        current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ":");
+
+        let (hex_end, value_json_end) = build_value_mappings(
+            data,
+            json_string,
+            mappings,
+            hex_pos,
+            current_json_pos,
+            options,
+            resumed,
+        )?;
+
+        current_json_pos = skip_whitespace(json_string, value_json_end);
+        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '}' {
+            current_json_pos += 1;
+        }
+        return Ok((hex_end, current_json_pos));
+    }
+    build_value_mappings(
+        data,
+        json_string,
+        mappings,
+        hex_pos,
+        json_pos,
+        options,
+        resumed,
+    )
+}
+
+/// Build position mappings for the value at `hex_pos` according to its format byte.
+fn build_value_mappings(
+    data: &[u8],
+    json_string: &str,
+    mappings: &mut Vec<PositionMapping>,
+    hex_pos: usize,
+    json_pos: usize,
+    options: &DecodeOptions,
+    resumed: &HashMap<usize, usize>,
+) -> Result<(usize, usize), DecodeError> {
     if hex_pos >= data.len() {
-        return Err("Unexpected end of data".to_string());
+        return Err(DecodeError::truncated(data, hex_pos, 1));
     }
 
     let byte = data[hex_pos];
@@ -400,57 +1234,17 @@ fn build_mappings(
         return Ok((hex_pos + 1, end_json_pos));
     }
 
-    // fixmap (0x80 - 0x8f)
-    if (0x80..=0x8f).contains(&byte) {
-        let count = (byte & 0x0f) as usize;
-        let mut current_json_pos = json_pos;
-        if json_pos < json_chars.len() && json_chars[current_json_pos] == '{' {
-            current_json_pos += 1;
-        }
-        let mut current_hex_pos = hex_pos + 1;
-
-        for _ in 0..count {
-            current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ",");
-
-            // Parse key
-            let key_hex_start = current_hex_pos;
-            let (key_value, key_end) = parse_msgpack_value(data, current_hex_pos)?;
-            current_hex_pos = key_end;
-
-            // Find key in JSON
-            if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '"' {
-                let key_str = serde_json::to_string(&key_value).unwrap_or_default();
-                let key_end_json_pos = current_json_pos + key_str.len();
-                mappings.push(PositionMapping {
-                    json_start: current_json_pos,
-                    json_end: key_end_json_pos,
-                    hex_start: key_hex_start,
-                    hex_end: current_hex_pos,
-                    mapping_type: MappingType::Key,
-                });
-                current_json_pos = key_end_json_pos;
-            }
-
-            current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ":");
-
-            // Parse value recursively
-            let (value_hex_end, value_json_end) = build_mappings(
-                data,
-                json_string,
-                mappings,
-                current_hex_pos,
-                current_json_pos,
-            )?;
-            current_hex_pos = value_hex_end;
-            current_json_pos = value_json_end;
-        }
-
-        current_json_pos = skip_whitespace(json_string, current_json_pos);
-        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '}' {
-            current_json_pos += 1;
-        }
-
-        return Ok((current_hex_pos, current_json_pos));
+    // fixmap (0x80 - 0x8f), map 16 and map 32
+    if (0x80..=0x8f).contains(&byte) || byte == 0xde || byte == 0xdf {
+        return build_map_mappings(
+            data,
+            json_string,
+            mappings,
+            hex_pos,
+            json_pos,
+            options,
+            resumed,
+        );
     }
 
     // fixarray (0x90 - 0x9f)
@@ -470,6 +1264,8 @@ fn build_mappings(
                 mappings,
                 current_hex_pos,
                 current_json_pos,
+                options,
+                resumed,
             )?;
             current_hex_pos = value_hex_end;
             current_json_pos = value_json_end;
@@ -486,18 +1282,16 @@ fn build_mappings(
     // fixstr (0xa0 - 0xbf)
     if (0xa0..=0xbf).contains(&byte) {
         let length = (byte & 0x1f) as usize;
-        let str_bytes = &data[hex_pos + 1..hex_pos + 1 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        let json_str = serde_json::to_string(&s).unwrap_or_default();
-        let end_json_pos = json_pos + json_str.len();
+        let hex_end = payload_end(data, hex_pos, 1, length)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
             hex_start: hex_pos,
-            hex_end: hex_pos + 1 + length,
+            hex_end,
             mapping_type: MappingType::Value,
         });
-        return Ok((hex_pos + 1 + length, end_json_pos));
+        return Ok((hex_end, end_json_pos));
     }
 
     // nil
@@ -539,16 +1333,10 @@ fn build_mappings(
         return Ok((hex_pos + 1, end_json_pos));
     }
 
-    // float 32
+    // float 32 (rendered as `{"$float32": <value>}`)
     if byte == 0xca {
-        let bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
-        let value = f32::from_be_bytes(bytes) as f64;
-        let value_str = if value.fract() == 0.0 {
-            format!("{}.0", value as i64)
-        } else {
-            value.to_string()
-        };
-        let end_json_pos = json_pos + value_str.len();
+        safe_array::<4>(data, hex_pos, 1)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
@@ -559,16 +1347,10 @@ fn build_mappings(
         return Ok((hex_pos + 5, end_json_pos));
     }
 
-    // float 64
+    // float 64 (a JSON number, or `{"$float64": "NaN"}` for non-finite values)
     if byte == 0xcb {
-        let bytes: [u8; 8] = safe_array(data, hex_pos + 1)?;
-        let value = f64::from_be_bytes(bytes);
-        let value_str = if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
-            format!("{}.0", value as i64)
-        } else {
-            value.to_string()
-        };
-        let end_json_pos = json_pos + value_str.len();
+        safe_array::<8>(data, hex_pos, 1)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
@@ -581,7 +1363,7 @@ fn build_mappings(
 
     // uint 8
     if byte == 0xcc {
-        let value = safe_byte(data, hex_pos + 1)?;
+        let value = safe_byte(data, hex_pos, 1)?;
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
         mappings.push(PositionMapping {
@@ -596,7 +1378,7 @@ fn build_mappings(
 
     // uint 16
     if byte == 0xcd {
-        let bytes: [u8; 2] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 2] = safe_array(data, hex_pos, 1)?;
         let value = u16::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -612,7 +1394,7 @@ fn build_mappings(
 
     // uint 32
     if byte == 0xce {
-        let bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 4] = safe_array(data, hex_pos, 1)?;
         let value = u32::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -628,7 +1410,7 @@ fn build_mappings(
 
     // uint 64
     if byte == 0xcf {
-        let bytes: [u8; 8] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 8] = safe_array(data, hex_pos, 1)?;
         let value = u64::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -644,7 +1426,7 @@ fn build_mappings(
 
     // int 8
     if byte == 0xd0 {
-        let value = safe_byte(data, hex_pos + 1)? as i8;
+        let value = safe_byte(data, hex_pos, 1)? as i8;
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
         mappings.push(PositionMapping {
@@ -659,7 +1441,7 @@ fn build_mappings(
 
     // int 16
     if byte == 0xd1 {
-        let bytes: [u8; 2] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 2] = safe_array(data, hex_pos, 1)?;
         let value = i16::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -675,7 +1457,7 @@ fn build_mappings(
 
     // int 32
     if byte == 0xd2 {
-        let bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 4] = safe_array(data, hex_pos, 1)?;
         let value = i32::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -691,7 +1473,7 @@ fn build_mappings(
 
     // int 64
     if byte == 0xd3 {
-        let bytes: [u8; 8] = safe_array(data, hex_pos + 1)?;
+        let bytes: [u8; 8] = safe_array(data, hex_pos, 1)?;
         let value = i64::from_be_bytes(bytes);
         let value_str = value.to_string();
         let end_json_pos = json_pos + value_str.len();
@@ -707,69 +1489,54 @@ fn build_mappings(
 
     // str 8
     if byte == 0xd9 {
-        let length = safe_byte(data, hex_pos + 1)? as usize;
-        if hex_pos + 2 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[hex_pos + 2..hex_pos + 2 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        let json_str = serde_json::to_string(&s).unwrap_or_default();
-        let end_json_pos = json_pos + json_str.len();
+        let length = safe_byte(data, hex_pos, 1)? as usize;
+        let hex_end = payload_end(data, hex_pos, 2, length)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
             hex_start: hex_pos,
-            hex_end: hex_pos + 2 + length,
+            hex_end,
             mapping_type: MappingType::Value,
         });
-        return Ok((hex_pos + 2 + length, end_json_pos));
+        return Ok((hex_end, end_json_pos));
     }
 
     // str 16
     if byte == 0xda {
-        let len_bytes: [u8; 2] = safe_array(data, hex_pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, hex_pos, 1)?;
         let length = u16::from_be_bytes(len_bytes) as usize;
-        if hex_pos + 3 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[hex_pos + 3..hex_pos + 3 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        let json_str = serde_json::to_string(&s).unwrap_or_default();
-        let end_json_pos = json_pos + json_str.len();
+        let hex_end = payload_end(data, hex_pos, 3, length)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
             hex_start: hex_pos,
-            hex_end: hex_pos + 3 + length,
+            hex_end,
             mapping_type: MappingType::Value,
         });
-        return Ok((hex_pos + 3 + length, end_json_pos));
+        return Ok((hex_end, end_json_pos));
     }
 
     // str 32
     if byte == 0xdb {
-        let len_bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, hex_pos, 1)?;
         let length = u32::from_be_bytes(len_bytes) as usize;
-        if hex_pos + 5 + length > data.len() {
-            return Err("Truncated string data".to_string());
-        }
-        let str_bytes = &data[hex_pos + 5..hex_pos + 5 + length];
-        let s = String::from_utf8_lossy(str_bytes).to_string();
-        let json_str = serde_json::to_string(&s).unwrap_or_default();
-        let end_json_pos = json_pos + json_str.len();
+        let hex_end = payload_end(data, hex_pos, 5, length)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
         mappings.push(PositionMapping {
             json_start: json_pos,
             json_end: end_json_pos,
             hex_start: hex_pos,
-            hex_end: hex_pos + 5 + length,
+            hex_end,
             mapping_type: MappingType::Value,
         });
-        return Ok((hex_pos + 5 + length, end_json_pos));
+        return Ok((hex_end, end_json_pos));
     }
 
     // array 16
     if byte == 0xdc {
-        let len_bytes: [u8; 2] = safe_array(data, hex_pos + 1)?;
+        let len_bytes: [u8; 2] = safe_array(data, hex_pos, 1)?;
         let count = u16::from_be_bytes(len_bytes) as usize;
         let mut current_json_pos = json_pos;
         if json_pos < json_chars.len() && json_chars[current_json_pos] == '[' {
@@ -785,6 +1552,8 @@ fn build_mappings(
                 mappings,
                 current_hex_pos,
                 current_json_pos,
+                options,
+                resumed,
             )?;
             current_hex_pos = value_hex_end;
             current_json_pos = value_json_end;
@@ -800,7 +1569,7 @@ fn build_mappings(
 
     // array 32
     if byte == 0xdd {
-        let len_bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
+        let len_bytes: [u8; 4] = safe_array(data, hex_pos, 1)?;
         let count = u32::from_be_bytes(len_bytes) as usize;
         let mut current_json_pos = json_pos;
         if json_pos < json_chars.len() && json_chars[current_json_pos] == '[' {
@@ -816,6 +1585,8 @@ fn build_mappings(
                 mappings,
                 current_hex_pos,
                 current_json_pos,
+                options,
+                resumed,
             )?;
             current_hex_pos = value_hex_end;
             current_json_pos = value_json_end;
@@ -829,120 +1600,261 @@ fn build_mappings(
         return Ok((current_hex_pos, current_json_pos));
     }
 
-    // map 16
-    if byte == 0xde {
-        let len_bytes: [u8; 2] = safe_array(data, hex_pos + 1)?;
-        let count = u16::from_be_bytes(len_bytes) as usize;
-        let mut current_json_pos = json_pos;
-        if json_pos < json_chars.len() && json_chars[current_json_pos] == '{' {
-            current_json_pos += 1;
-        }
-        let mut current_hex_pos = hex_pos + 3;
-
-        for _ in 0..count {
-            current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ",");
-
-            let key_hex_start = current_hex_pos;
-            let (key_value, key_end) = parse_msgpack_value(data, current_hex_pos)?;
-            current_hex_pos = key_end;
-
-            if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '"' {
-                let key_str = serde_json::to_string(&key_value).unwrap_or_default();
-                let key_end_json_pos = current_json_pos + key_str.len();
-                mappings.push(PositionMapping {
-                    json_start: current_json_pos,
-                    json_end: key_end_json_pos,
-                    hex_start: key_hex_start,
-                    hex_end: current_hex_pos,
-                    mapping_type: MappingType::Key,
-                });
-                current_json_pos = key_end_json_pos;
-            }
-
-            current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ":");
+    // bin 8/16/32, fixext 1/2/4/8/16 and ext 8/16/32 (rendered as tagged objects)
+    if (0xc4..=0xc9).contains(&byte) || (0xd4..=0xd8).contains(&byte) {
+        let (_, end_pos) = parse_msgpack_value(data, hex_pos, options)?;
+        let end_json_pos = skip_json_value(json_string, json_pos);
+        mappings.push(PositionMapping {
+            json_start: json_pos,
+            json_end: end_json_pos,
+            hex_start: hex_pos,
+            hex_end: end_pos,
+            mapping_type: MappingType::Value,
+        });
+        return Ok((end_pos, end_json_pos));
+    }
 
-            let (value_hex_end, value_json_end) = build_mappings(
-                data,
-                json_string,
-                mappings,
-                current_hex_pos,
-                current_json_pos,
-            )?;
-            current_hex_pos = value_hex_end;
-            current_json_pos = value_json_end;
-        }
+    // For other types, skip them
+    let (_, end_pos) = parse_msgpack_value(data, hex_pos, options)?;
+    Ok((end_pos, json_pos))
+}
 
+/// Build position mappings for the map whose header is at `map_pos`.
+/// Handles both the JSON object form and the `{"$map": [[key, value], ...]}` form.
+fn build_map_mappings(
+    data: &[u8],
+    json_string: &str,
+    mappings: &mut Vec<PositionMapping>,
+    map_pos: usize,
+    json_pos: usize,
+    options: &DecodeOptions,
+    resumed: &HashMap<usize, usize>,
+) -> Result<(usize, usize), DecodeError> {
+    let json_chars: Vec<char> = json_string.chars().collect();
+    let (count, header_len) = match format::read_length_header(data, map_pos) {
+        Ok(Some(header)) => header,
+        _ => return Err(DecodeError::truncated(data, map_pos, 1)),
+    };
+    let hex_pos = map_pos + header_len;
+    // Limits were checked when the whole value was validated
+    let mut map = MapEntries::default();
+    let decoded = decode_map_entries(
+        data,
+        hex_pos,
+        count,
+        options,
+        0,
+        &mut DecodeReport::default(),
+        &mut map,
+    );
+    let MapEntries {
+        mut entries,
+        key_ranges,
+        failed_key,
+    } = map;
+    // In partial mode a map the data ran out in maps the entries that are there, the way
+    // the decoder rendered them, and the error is passed on once they are mapped
+    let (hex_end, cut) = match decoded {
+        Ok(end) => (end, None),
+        Err(e) if options.partial => (data.len(), Some(e)),
+        Err(e) => return Err(e),
+    };
+    let cut_in_key = cut.is_some() && failed_key.is_none();
+    if let Some(key) = failed_key {
+        // The value is mapped as far as it goes below; only its key matters here
+        entries.push((key, serde_json::Value::Null));
+    }
+    let as_pairs = cut_in_key || renders_as_pairs(&entries, options);
+
+    let mut current_json_pos = json_pos;
+    if json_pos < json_chars.len() && json_chars[current_json_pos] == '{' {
+        current_json_pos += 1;
+    }
+    if as_pairs {
+        // Step over `"$map":` and the opening bracket of the pairs array
         current_json_pos = skip_whitespace(json_string, current_json_pos);
-        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '}' {
+        current_json_pos = skip_json_value(json_string, current_json_pos);
+        current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ":");
+        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '[' {
             current_json_pos += 1;
         }
-
-        return Ok((current_hex_pos, current_json_pos));
     }
 
-    // map 32
-    if byte == 0xdf {
-        let len_bytes: [u8; 4] = safe_array(data, hex_pos + 1)?;
-        let count = u32::from_be_bytes(len_bytes) as usize;
-        let mut current_json_pos = json_pos;
-        if json_pos < json_chars.len() && json_chars[current_json_pos] == '{' {
-            current_json_pos += 1;
-        }
-        let mut current_hex_pos = hex_pos + 5;
+    let shown: Vec<Vec<usize>> = if as_pairs {
+        (0..entries.len()).map(|index| vec![index]).collect()
+    } else {
+        object_entry_order(&entries)
+    };
 
-        for _ in 0..count {
-            current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ",");
-
-            let key_hex_start = current_hex_pos;
-            let (key_value, key_end) = parse_msgpack_value(data, current_hex_pos)?;
-            current_hex_pos = key_end;
+    for occurrences in shown {
+        current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ",");
+        let (&index, earlier) = occurrences.split_last().unwrap_or((&0, &[]));
+        let key_value = &entries[index].0;
+        let (key_hex_start, key_hex_end) = key_ranges[index];
 
+        if as_pairs {
+            // Each entry is a `[key, value]` array; the key may be any JSON value
+            if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '[' {
+                current_json_pos += 1;
+            }
+            current_json_pos = skip_whitespace(json_string, current_json_pos);
+            let key_end_json_pos = skip_json_value(json_string, current_json_pos);
+            mappings.push(PositionMapping {
+                json_start: current_json_pos,
+                json_end: key_end_json_pos,
+                hex_start: key_hex_start,
+                hex_end: key_hex_end,
+                mapping_type: MappingType::Key,
+            });
+            current_json_pos = skip_whitespace_and_delimiters(json_string, key_end_json_pos, ",");
+        } else {
+            // Find key in JSON
             if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '"' {
-                let key_str = serde_json::to_string(&key_value).unwrap_or_default();
+                let key_str = serde_json::to_string(&key_to_string(key_value)).unwrap_or_default();
                 let key_end_json_pos = current_json_pos + key_str.len();
                 mappings.push(PositionMapping {
                     json_start: current_json_pos,
                     json_end: key_end_json_pos,
                     hex_start: key_hex_start,
-                    hex_end: current_hex_pos,
+                    hex_end: key_hex_end,
                     mapping_type: MappingType::Key,
                 });
+                // The entries the shown key overwrote map to it as well
+                for &duplicate in earlier {
+                    let (hex_start, hex_end) = key_ranges[duplicate];
+                    mappings.push(PositionMapping {
+                        json_start: current_json_pos,
+                        json_end: key_end_json_pos,
+                        hex_start,
+                        hex_end,
+                        mapping_type: MappingType::DuplicateKey,
+                    });
+                }
                 current_json_pos = key_end_json_pos;
             }
-
             current_json_pos = skip_whitespace_and_delimiters(json_string, current_json_pos, ":");
+        }
 
-            let (value_hex_end, value_json_end) = build_mappings(
-                data,
-                json_string,
-                mappings,
-                current_hex_pos,
-                current_json_pos,
-            )?;
-            current_hex_pos = value_hex_end;
-            current_json_pos = value_json_end;
+        // Parse value recursively
+        current_json_pos = match build_mappings(
+            data,
+            json_string,
+            mappings,
+            key_hex_end,
+            current_json_pos,
+            options,
+            resumed,
+        ) {
+            Ok((_, value_json_end)) => value_json_end,
+            // A duplicate key can show the cut-off value before entries that are complete
+            Err(_) if cut.is_some() => {
+                let value_json_pos = skip_whitespace(json_string, current_json_pos);
+                skip_json_value(json_string, value_json_pos)
+            }
+            Err(e) => return Err(e),
+        };
+
+        if as_pairs {
+            current_json_pos = skip_whitespace(json_string, current_json_pos);
+            if current_json_pos < json_chars.len() && json_chars[current_json_pos] == ']' {
+                current_json_pos += 1;
+            }
         }
+    }
 
+    if as_pairs {
         current_json_pos = skip_whitespace(json_string, current_json_pos);
-        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '}' {
+        if current_json_pos < json_chars.len() && json_chars[current_json_pos] == ']' {
             current_json_pos += 1;
         }
-
-        return Ok((current_hex_pos, current_json_pos));
+    }
+    if let Some(e) = cut {
+        return Err(e);
+    }
+    current_json_pos = skip_whitespace(json_string, current_json_pos);
+    if current_json_pos < json_chars.len() && json_chars[current_json_pos] == '}' {
+        current_json_pos += 1;
     }
 
-    // For other types, skip them
-    let (_, end_pos) = parse_msgpack_value(data, hex_pos)?;
-    Ok((end_pos, json_pos))
+    Ok((hex_end, current_json_pos))
 }
 
-/// Creates position mappings between msgpack bytes and JSON text.
-pub fn create_position_mappings(msgpack_data: &[u8], json_string: &str) -> Vec<PositionMapping> {
+/// Indices of the entries behind each key a JSON object shows, in its key order. A repeated
+/// key keeps the position of its first entry but the value of its last, as `serde_json::Map`
+/// does, so the last index of each key is the entry shown.
+fn object_entry_order(entries: &[(serde_json::Value, serde_json::Value)]) -> Vec<Vec<usize>> {
+    let mut order: Vec<Vec<usize>> = Vec::new();
+    let mut slots: HashMap<String, usize> = HashMap::new();
+    for (index, (key, _)) in entries.iter().enumerate() {
+        match slots.entry(key_to_string(key)) {
+            Entry::Occupied(slot) => order[*slot.get()].push(index),
+            Entry::Vacant(slot) => {
+                slot.insert(order.len());
+                order.push(vec![index]);
+            }
+        }
+    }
+    order
+}
+
+/// Creates position mappings between msgpack bytes and JSON text
+/// that was decoded with the given options.
+pub fn create_position_mappings(
+    msgpack_data: &[u8],
+    json_string: &str,
+    options: &DecodeOptions,
+) -> Vec<PositionMapping> {
     let mut mappings = Vec::new();
-    let _ = build_mappings(msgpack_data, json_string, &mut mappings, 0, 0);
+    if options.stream == StreamFormat::Off {
+        // Decode first so hostile input is rejected before the mapper recurses into it
+        let Ok((_, _, report)) = parse_msgpack_value_with_report(msgpack_data, 0, options) else {
+            return mappings;
+        };
+        let resumed = report.skipped.into_iter().collect();
+        let _ = build_mappings(
+            msgpack_data,
+            json_string,
+            &mut mappings,
+            0,
+            0,
+            options,
+            &resumed,
+        );
+    } else {
+        let _ = build_stream_mappings(msgpack_data, json_string, &mut mappings, options);
+    }
     mappings
 }
 
+/// Map each concatenated top-level value to its array element or NDJSON line.
+fn build_stream_mappings(
+    data: &[u8],
+    json_string: &str,
+    mappings: &mut Vec<PositionMapping>,
+    options: &DecodeOptions,
+) -> Result<(), DecodeError> {
+    let mut hex_pos = 0;
+    let mut json_pos = skip_whitespace(json_string, 0);
+    if options.stream == StreamFormat::JsonArray {
+        json_pos = skip_whitespace_and_delimiters(json_string, json_pos, "[");
+    }
+    while hex_pos < data.len() {
+        json_pos = skip_whitespace_and_delimiters(json_string, json_pos, ",");
+        let (_, _, report) = parse_msgpack_value_with_report(data, hex_pos, options)?;
+        let resumed = report.skipped.into_iter().collect();
+        (hex_pos, json_pos) = build_mappings(
+            data,
+            json_string,
+            mappings,
+            hex_pos,
+            json_pos,
+            options,
+            &resumed,
+        )?;
+    }
+    Ok(())
+}
+
 /// Finds the hex byte range that corresponds to a given JSON text selection.
 pub fn find_hex_range_for_json_selection(
     mappings: &[PositionMapping],
@@ -953,6 +1865,7 @@ pub fn find_hex_range_for_json_selection(
     let overlapping: Vec<_> = mappings
         .iter()
         .filter(|m| m.json_start < json_sel_end && m.json_end > json_sel_start)
+        .filter(|m| m.mapping_type != MappingType::DuplicateKey)
         .collect();
 
     if overlapping.is_empty() {
@@ -966,17 +1879,118 @@ pub fn find_hex_range_for_json_selection(
     Some((hex_start, hex_end))
 }
 
-/// Converts byte range to character range in the space-separated hex string.
-/// Each byte takes 3 characters (2 hex digits + 1 space), except the last byte.
-pub fn byte_range_to_hex_char_range(hex_start: usize, hex_end: usize) -> (usize, usize) {
-    let char_start = hex_start * 3;
-    let char_end = hex_end * 3 - 1; // -1 to not include trailing space of last byte
-    (char_start, char_end)
+/// Finds every hex byte range to highlight for a JSON text selection: the range
+/// `find_hex_range_for_json_selection` finds, then the earlier occurrences of any
+/// repeated object key that is selected.
+pub fn find_hex_ranges_for_json_selection(
+    mappings: &[PositionMapping],
+    json_sel_start: usize,
+    json_sel_end: usize,
+) -> Vec<ByteRange> {
+    let duplicates = mappings
+        .iter()
+        .filter(|m| m.json_start < json_sel_end && m.json_end > json_sel_start)
+        .filter(|m| m.mapping_type == MappingType::DuplicateKey)
+        .map(|m| (m.hex_start, m.hex_end));
+    find_hex_range_for_json_selection(mappings, json_sel_start, json_sel_end)
+        .into_iter()
+        .chain(duplicates)
+        .collect()
+}
+
+/// How the hex text is laid out, which determines where each byte's characters are.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HexLayout {
+    /// One line of space-separated bytes: `81 A5 68`
+    #[default]
+    Inline,
+    /// `hexdump -C` style rows of 16 bytes with an offset column and an ASCII column
+    Dump,
+}
+
+/// Bytes per row in the dump layout
+const DUMP_ROW_BYTES: usize = 16;
+/// Column of the first hex cell in a dump row, after the 8-digit offset and two spaces
+const DUMP_HEX_START: usize = 10;
+/// Column of the first ASCII cell in a dump row, after the hex cells and ` |`
+const DUMP_ASCII_START: usize = 61;
+/// Characters per dump row, including the closing `|` and the newline
+const DUMP_ROW_LEN: usize = 79;
+
+/// Column of the hex cell for byte `index` within a dump row; an extra space
+/// separates the first and second group of eight bytes
+fn dump_hex_column(index: usize) -> usize {
+    DUMP_HEX_START + index * 3 + usize::from(index >= DUMP_ROW_BYTES / 2)
+}
+
+/// Converts a byte range to the character ranges it occupies in the hex text.
+/// In the inline layout each byte takes 3 characters (2 hex digits + 1 space), except the
+/// last byte, giving a single range. In the dump layout every row spanned contributes one
+/// range for its hex cells and one for its ASCII cells.
+pub fn byte_range_to_hex_char_range(
+    hex_start: usize,
+    hex_end: usize,
+    layout: HexLayout,
+) -> Vec<(usize, usize)> {
+    if hex_start >= hex_end {
+        return Vec::new();
+    }
+    match layout {
+        // -1 to not include trailing space of last byte
+        HexLayout::Inline => vec![(hex_start * 3, hex_end * 3 - 1)],
+        HexLayout::Dump => {
+            let mut ranges = Vec::new();
+            for row in hex_start / DUMP_ROW_BYTES..hex_end.div_ceil(DUMP_ROW_BYTES) {
+                let row_start = row * DUMP_ROW_BYTES;
+                let first = hex_start.max(row_start) - row_start;
+                let last = hex_end.min(row_start + DUMP_ROW_BYTES) - row_start;
+                let line = row * DUMP_ROW_LEN;
+                ranges.push((
+                    line + dump_hex_column(first),
+                    line + dump_hex_column(last - 1) + 2,
+                ));
+                ranges.push((
+                    line + DUMP_ASCII_START + first,
+                    line + DUMP_ASCII_START + last,
+                ));
+            }
+            ranges
+        }
+    }
+}
+
+/// Render bytes in the dump layout: `00000000  81 A5 68 ...  |..h|`
+pub fn format_hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(DUMP_ROW_BYTES)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let mut line = format!("{:08X}  ", row * DUMP_ROW_BYTES);
+            for index in 0..DUMP_ROW_BYTES {
+                match chunk.get(index) {
+                    Some(byte) => line.push_str(&format!("{:02X} ", byte)),
+                    None => line.push_str("   "),
+                }
+                if index == DUMP_ROW_BYTES / 2 - 1 {
+                    line.push(' ');
+                }
+            }
+            line.push_str(" |");
+            line.extend(chunk.iter().map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            }));
+            line.push('|');
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::{DecodeErrorKind, LimitKind};
 
     #[test]
     fn test_create_position_mappings_simple_object() {
@@ -984,7 +1998,7 @@ mod tests {
         let msgpack = vec![0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b];
         let json = "{\n  \"hello\": 123\n}";
 
-        let mappings = create_position_mappings(&msgpack, json);
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
 
         assert_eq!(mappings.len(), 2);
 
@@ -1011,7 +2025,7 @@ mod tests {
         let msgpack = vec![0x93, 0x01, 0x02, 0x03];
         let json = "[\n  1,\n  2,\n  3\n]";
 
-        let mappings = create_position_mappings(&msgpack, json);
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
 
         assert_eq!(mappings.len(), 3);
         assert!(mappings
@@ -1033,16 +2047,607 @@ mod tests {
         let msgpack = vec![0x93, 0xc0, 0xc3, 0xc2];
         let json = "[\n  null,\n  true,\n  false\n]";
 
-        let mappings = create_position_mappings(&msgpack, json);
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
 
         assert_eq!(mappings.len(), 3);
     }
 
+    #[test]
+    fn test_create_position_mappings_ext() {
+        // [fixext1(type 1, 0x2a), 7] in msgpack: 92 D4 01 2A 07
+        let msgpack = vec![0x92, 0xd4, 0x01, 0x2a, 0x07];
+        let json = "[\n  {\n    \"$ext\": {\n      \"type\": 1,\n      \"data\": \"Kg==\"\n    }\n  },\n  7\n]";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].hex_start, 1);
+        assert_eq!(mappings[0].hex_end, 4);
+        assert_eq!(
+            &json[mappings[0].json_start..mappings[0].json_start + 1],
+            "{"
+        );
+        assert_eq!(&json[mappings[0].json_end - 1..mappings[0].json_end], "}");
+        assert_eq!(mappings[1].hex_start, 4);
+        assert_eq!(&json[mappings[1].json_start..mappings[1].json_end], "7");
+    }
+
+    #[test]
+    fn test_parse_ext_values() {
+        let (value, end) =
+            parse_msgpack_value(&[0xd5, 0x02, 0xab, 0xcd], 0, &DecodeOptions::default()).unwrap();
+        assert_eq!(value["$ext"]["type"], 2);
+        assert_eq!(value["$ext"]["data"], "q80=");
+        assert_eq!(end, 4);
+
+        // ext 8 declaring more data than available
+        assert!(
+            parse_msgpack_value(&[0xc7, 0x05, 0x01, 0x00], 0, &DecodeOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_position_mappings_timestamp() {
+        // {"t": timestamp32(0)} in msgpack: 81 A1 74 D6 FF 00 00 00 00
+        let msgpack = vec![0x81, 0xa1, 0x74, 0xd6, 0xff, 0x00, 0x00, 0x00, 0x00];
+        let json = "{\n  \"t\": {\n    \"$timestamp\": \"1970-01-01T00:00:00Z\"\n  }\n}";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[1].hex_start, 3);
+        assert_eq!(mappings[1].hex_end, 9);
+        assert_eq!(
+            &json[mappings[1].json_start..mappings[1].json_end],
+            "{\n    \"$timestamp\": \"1970-01-01T00:00:00Z\"\n  }"
+        );
+    }
+
+    #[test]
+    fn test_create_position_mappings_bin_keeps_following_values_aligned() {
+        // [bin8(01 02), 5] in msgpack: 92 C4 02 01 02 05
+        let msgpack = vec![0x92, 0xc4, 0x02, 0x01, 0x02, 0x05];
+        let json = "[\n  {\n    \"$bin\": \"AQI=\"\n  },\n  5\n]";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].hex_start, 1);
+        assert_eq!(mappings[0].hex_end, 5);
+        assert_eq!(
+            &json[mappings[0].json_start..mappings[0].json_end],
+            "{\n    \"$bin\": \"AQI=\"\n  }"
+        );
+        assert_eq!(mappings[1].hex_start, 5);
+        assert_eq!(&json[mappings[1].json_start..mappings[1].json_end], "5");
+    }
+
+    #[test]
+    fn test_parse_non_string_keys() {
+        // {1: "a", "b": 2} in msgpack: 82 01 A1 61 A1 62 02
+        let msgpack = [0x82, 0x01, 0xa1, 0x61, 0xa1, 0x62, 0x02];
+
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &DecodeOptions::default()).unwrap();
+        assert_eq!(value.to_string(), r#"{"1":"a","b":2}"#);
+
+        let options = DecodeOptions {
+            preserve_map_keys: true,
+            ..Default::default()
+        };
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        assert_eq!(value.to_string(), r#"{"$map":[[1,"a"],["b",2]]}"#);
+    }
+
+    #[test]
+    fn test_create_position_mappings_non_string_keys() {
+        // {1: "a", [2]: 3} in msgpack: 82 01 A1 61 91 02 03
+        let msgpack = vec![0x82, 0x01, 0xa1, 0x61, 0x91, 0x02, 0x03];
+        let options = DecodeOptions {
+            preserve_map_keys: true,
+            ..Default::default()
+        };
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        let json = serde_json::to_string_pretty(&value).unwrap();
+
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+
+        let spans: Vec<_> = mappings
+            .iter()
+            .map(|m| (&json[m.json_start..m.json_end], m.hex_start, m.hex_end))
+            .collect();
+        assert_eq!(spans[0], ("1", 1, 2));
+        assert_eq!(spans[1], ("\"a\"", 2, 4));
+        assert_eq!(spans[2].1..spans[2].2, 4..6);
+        assert!(spans[2].0.starts_with('[') && spans[2].0.ends_with(']'));
+        assert_eq!(spans[3], ("3", 6, 7));
+        assert_eq!(mappings[0].mapping_type, MappingType::Key);
+    }
+
+    #[test]
+    fn test_create_position_mappings_stringified_keys() {
+        // {1: 2} in msgpack: 81 01 02, rendered as {"1": 2}
+        let msgpack = vec![0x81, 0x01, 0x02];
+        let json = "{\n  \"1\": 2\n}";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(&json[mappings[0].json_start..mappings[0].json_end], "\"1\"");
+        assert_eq!(&json[mappings[1].json_start..mappings[1].json_end], "2");
+    }
+
+    #[test]
+    fn test_parse_wire_exact_annotations() {
+        let options = DecodeOptions {
+            wire_exact: true,
+            ..Default::default()
+        };
+        // [uint16 1, 200 as uint8, str8 "a", array16 [-1 as int8]]
+        let msgpack = [
+            0x94, 0xcd, 0x00, 0x01, 0xcc, 0xc8, 0xd9, 0x01, 0x61, 0xdc, 0x00, 0x01, 0xd0, 0xff,
+        ];
+        let (value, end) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        assert_eq!(end, msgpack.len());
+        assert_eq!(
+            value.to_string(),
+            r#"[{"$uint16":1},200,{"$str8":"a"},{"$array16":[{"$int8":-1}]}]"#
+        );
+
+        // Without the option the same payload is rendered plainly
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &DecodeOptions::default()).unwrap();
+        assert_eq!(value.to_string(), r#"[1,200,"a",[-1]]"#);
+    }
+
+    #[test]
+    fn test_create_position_mappings_wire_exact() {
+        let options = DecodeOptions {
+            wire_exact: true,
+            ..Default::default()
+        };
+        // {"a": array16 [uint16 1], "b": 2}
+        let msgpack = vec![
+            0x82, 0xa1, 0x61, 0xdc, 0x00, 0x01, 0xcd, 0x00, 0x01, 0xa1, 0x62, 0x02,
+        ];
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        let json = serde_json::to_string_pretty(&value).unwrap();
+
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+
+        let spans: Vec<_> = mappings
+            .iter()
+            .map(|m| (&json[m.json_start..m.json_end], m.hex_start, m.hex_end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("\"a\"", 1, 3),
+                ("1", 6, 9),
+                ("\"b\"", 9, 11),
+                ("2", 11, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_float32_shortest_representation() {
+        // float32 0.1 in msgpack: CA 3D CC CC CD
+        let (value, end) = parse_msgpack_value(
+            &[0xca, 0x3d, 0xcc, 0xcc, 0xcd],
+            0,
+            &DecodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(value.to_string(), r#"{"$float32":0.1}"#);
+        assert_eq!(end, 5);
+    }
+
+    #[test]
+    fn test_create_position_mappings_float32() {
+        // [float32 0.1, 1] in msgpack: 92 CA 3D CC CC CD 01
+        let msgpack = vec![0x92, 0xca, 0x3d, 0xcc, 0xcc, 0xcd, 0x01];
+        let json = "[\n  {\n    \"$float32\": 0.1\n  },\n  1\n]";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(
+            &json[mappings[0].json_start..mappings[0].json_end],
+            "{\n    \"$float32\": 0.1\n  }"
+        );
+        assert_eq!((mappings[0].hex_start, mappings[0].hex_end), (1, 6));
+        assert_eq!(&json[mappings[1].json_start..mappings[1].json_end], "1");
+    }
+
+    #[test]
+    fn test_parse_special_floats() {
+        let options = DecodeOptions::default();
+        let parse = |bytes: &[u8]| parse_msgpack_value(bytes, 0, &options).unwrap().0;
+
+        let nan = [&[0xcb][..], &f64::NAN.to_be_bytes()].concat();
+        assert_eq!(parse(&nan).to_string(), r#"{"$float64":"NaN"}"#);
+        let neg_inf = [&[0xcb][..], &f64::NEG_INFINITY.to_be_bytes()].concat();
+        assert_eq!(parse(&neg_inf).to_string(), r#"{"$float64":"-Infinity"}"#);
+        let neg_zero = [&[0xcb][..], &(-0.0f64).to_be_bytes()].concat();
+        assert_eq!(parse(&neg_zero).to_string(), "-0.0");
+        let inf32 = [&[0xca][..], &f32::INFINITY.to_be_bytes()].concat();
+        assert_eq!(parse(&inf32).to_string(), r#"{"$float32":"Infinity"}"#);
+        let signalling_nan32 = [0xca, 0x7f, 0x80, 0x00, 0x01];
+        assert_eq!(
+            parse(&signalling_nan32).to_string(),
+            r#"{"$float32":"NaN:0x7f800001"}"#
+        );
+    }
+
+    #[test]
+    fn test_resync_deeply_nested_corruption() {
+        let options = DecodeOptions {
+            resync: true,
+            ..Default::default()
+        };
+        // [[[...[<0xc1>]...]]] 60 levels deep: every level fails and is resynced from,
+        // which must not retry the levels inside it again
+        let mut msgpack = vec![0x91; 60];
+        msgpack.push(0xc1);
+        assert!(parse_msgpack_value(&msgpack, 0, &options).is_err());
+
+        // The same with a long run of corrupt bytes before a value to resume at
+        msgpack.extend([0xc1; 5000]);
+        msgpack.push(0x07);
+        let (value, end, report) = parse_msgpack_value_with_report(&msgpack, 0, &options).unwrap();
+        assert_eq!(end, msgpack.len());
+        assert_eq!(report.skipped, vec![(60, msgpack.len() - 1)]);
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, format!("{}7{}", "[".repeat(60), "]".repeat(60)));
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+        let seven = json.find('7').unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, seven, seven + 1),
+            Some((msgpack.len() - 1, msgpack.len()))
+        );
+
+        // An element does not resume where the rest of its array would no longer fit,
+        // so here the array itself is skipped instead
+        let (value, _, report) =
+            parse_msgpack_value_with_report(&[0x92, 0xc1, 0x01], 0, &options).unwrap();
+        assert_eq!(value, serde_json::json!(1));
+        assert_eq!(report.skipped, vec![(0, 2)]);
+    }
+
+    #[test]
+    fn test_create_position_mappings_resync() {
+        let options = DecodeOptions {
+            resync: true,
+            ..Default::default()
+        };
+        let spans = |msgpack: &[u8], needles: &[&str]| {
+            let (value, _) = parse_msgpack_value(msgpack, 0, &options).unwrap();
+            let json = serde_json::to_string(&value).unwrap();
+            let mappings = create_position_mappings(msgpack, &json, &options);
+            needles
+                .iter()
+                .map(|needle| {
+                    let start = json.find(needle).unwrap();
+                    find_hex_range_for_json_selection(&mappings, start, start + needle.len())
+                })
+                .collect::<Vec<_>>()
+        };
+        // [<0xc1>, 1, 2]
+        assert_eq!(
+            spans(&[0x92, 0xc1, 0x01, 0x02], &["1", "2"]),
+            vec![Some((2, 3)), Some((3, 4))]
+        );
+        // {<0xc1> "a": 1}
+        assert_eq!(
+            spans(&[0x81, 0xc1, 0xa1, b'a', 0x01], &["\"a\"", "1"]),
+            vec![Some((2, 4)), Some((4, 5))]
+        );
+    }
+
+    #[test]
+    fn test_create_position_mappings_partial() {
+        // [7, "xy<cut>"]
+        let msgpack = [0x92, 0x07, 0xa4, b'x', b'y'];
+        let options = DecodeOptions {
+            partial: true,
+            ..Default::default()
+        };
+        let (value, end) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        assert_eq!(end, msgpack.len());
+        let json = serde_json::to_string_pretty(&value).unwrap();
+
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+        let seven = json.find('7').unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, seven, seven + 1),
+            Some((1, 2))
+        );
+        assert!(create_position_mappings(&msgpack, &json, &DecodeOptions::default()).is_empty());
+
+        // An array cut off after two of its three elements
+        let msgpack = [0x93, 0x01, 0x02];
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        let json = serde_json::to_string(&value).unwrap();
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+        let two = json.find('2').unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, two, two + 1),
+            Some((2, 3))
+        );
+
+        let spans = |msgpack: &[u8], needles: &[&str]| {
+            let (value, _) = parse_msgpack_value(msgpack, 0, &options).unwrap();
+            let json = serde_json::to_string(&value).unwrap();
+            let mappings = create_position_mappings(msgpack, &json, &options);
+            needles
+                .iter()
+                .map(|needle| {
+                    let start = json.find(needle).unwrap();
+                    find_hex_range_for_json_selection(&mappings, start, start + needle.len())
+                })
+                .collect::<Vec<_>>()
+        };
+        // {"a": 1, "b": "xy<cut>"}
+        assert_eq!(
+            spans(
+                &[0x82, 0xa1, b'a', 0x01, 0xa1, b'b', 0xa4, b'x', b'y'],
+                &["\"a\"", "1", "\"b\""]
+            ),
+            vec![Some((1, 3)), Some((3, 4)), Some((4, 6))]
+        );
+        // [7, {"a": 1, "b"<cut>}]
+        assert_eq!(
+            spans(
+                &[0x92, 0x07, 0x82, 0xa1, b'a', 0x01, 0xa1, b'b'],
+                &["7", "\"a\"", "1", "\"b\""]
+            ),
+            vec![Some((1, 2)), Some((3, 5)), Some((5, 6)), Some((6, 8))]
+        );
+        // {"a": 1, "x<cut>}, shown as `$map` pairs
+        assert_eq!(
+            spans(&[0x82, 0xa1, b'a', 0x01, 0xa3, b'x'], &["\"a\"", "1"]),
+            vec![Some((1, 3)), Some((3, 4))]
+        );
+        // {"a": 1, "b": 5, "a": "x<cut>}, where the cut-off value is shown first
+        assert_eq!(
+            spans(
+                &[0x83, 0xa1, b'a', 0x01, 0xa1, b'b', 0x05, 0xa1, b'a', 0xa2, b'x'],
+                &["\"b\"", "5"]
+            ),
+            vec![Some((4, 6)), Some((6, 7))]
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // {"a": [1, -2]} then a truncated str 8
+        let msgpack = [0x81, 0xa1, b'a', 0x92, 0x01, 0xfe, 0xd9, 0x05, b'x'];
+        let (tokens, error) = disassemble(&msgpack, &DecodeOptions::default());
+        let listed: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.header, format::format_name(t.format), t.length, t.depth))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ((0, 1), "fixmap", Some(1), 0),
+                ((1, 2), "fixstr", Some(1), 1),
+                ((3, 4), "fixarray", Some(2), 1),
+                ((4, 5), "positive fixint", None, 2),
+                ((5, 6), "negative fixint", None, 2),
+            ]
+        );
+        assert_eq!(tokens[1].value, Some(serde_json::json!("a")));
+        assert_eq!(tokens[3].value, Some(serde_json::json!(1)));
+        assert_eq!(tokens[2].value, None);
+        assert_eq!(error.map(|e| e.offset), Some(6));
+    }
+
+    #[test]
+    fn test_lint_wide_headers() {
+        // [uint64 1, int32 70000, map16 {"k": int8 -5}]
+        let msgpack = [
+            0x93, 0xcf, 0, 0, 0, 0, 0, 0, 0, 1, 0xd2, 0, 1, 0x11, 0x70, 0xde, 0, 1, 0xa1, b'k',
+            0xd0, 0xfb,
+        ];
+        let options = DecodeOptions {
+            lint: true,
+            ..Default::default()
+        };
+        let (_, _, report) = parse_msgpack_value_with_report(&msgpack, 0, &options).unwrap();
+        let headers: Vec<_> = report
+            .wide_headers
+            .iter()
+            .map(|header| (header.range, header.saved()))
+            .collect();
+        // int32 is the smallest signed format for 70000, and uint32 is no smaller
+        assert_eq!(headers, vec![((1, 10), 8), ((20, 22), 1), ((15, 18), 2)]);
+        assert_eq!(
+            report.wide_headers[2].to_string(),
+            "map16 at offset 15 (0xf) declares length 1; fixmap would save 2 bytes"
+        );
+        assert_eq!(
+            report.wide_headers[1].to_string(),
+            "int8 at offset 20 (0x14) holds -5; negative fixint would save 1 byte"
+        );
+
+        let (_, _, report) =
+            parse_msgpack_value_with_report(&msgpack, 0, &DecodeOptions::default()).unwrap();
+        assert!(report.wide_headers.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_map_keys() {
+        // {"a": 1, "b": 2, "a": 3}
+        let msgpack = [0x83, 0xa1, b'a', 0x01, 0xa1, b'b', 0x02, 0xa1, b'a', 0x03];
+        let options = DecodeOptions::default();
+        let (value, _, report) = parse_msgpack_value_with_report(&msgpack, 0, &options).unwrap();
+        assert_eq!(value, serde_json::json!({"a": 3, "b": 2}));
+        assert_eq!(
+            report.duplicate_keys,
+            vec![DuplicateKey {
+                range: (7, 9),
+                key: "\"a\"".to_string(),
+                kept: false
+            }]
+        );
+
+        // The object shows "a" first, holding the value of the last entry
+        let json = serde_json::to_string(&value).unwrap();
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+        let three = json.find('3').unwrap();
+        let two = json.find('2').unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, three, three + 1),
+            Some((9, 10))
+        );
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, two, two + 1),
+            Some((6, 7))
+        );
+
+        // Selecting the key highlights every entry it stands for
+        let key = json.find("\"a\"").unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, key, key + 3),
+            Some((7, 9))
+        );
+        assert_eq!(
+            find_hex_ranges_for_json_selection(&mappings, key, key + 3),
+            vec![(7, 9), (1, 3)]
+        );
+        assert_eq!(
+            find_hex_ranges_for_json_selection(&mappings, three, three + 1),
+            vec![(9, 10)]
+        );
+
+        let options = DecodeOptions {
+            keep_duplicate_keys: true,
+            ..Default::default()
+        };
+        let (value, _) = parse_msgpack_value(&msgpack, 0, &options).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"$map": [["a", 1], ["b", 2], ["a", 3]]})
+        );
+        let json = serde_json::to_string(&value).unwrap();
+        let mappings = create_position_mappings(&msgpack, &json, &options);
+        let one = json.find('1').unwrap();
+        assert_eq!(
+            find_hex_range_for_json_selection(&mappings, one, one + 1),
+            Some((3, 4))
+        );
+
+        // {1: 10, "1": 11}: distinct keys as pairs, the same key once stringified
+        let msgpack = [0x82, 0x01, 0x0a, 0xa1, b'1', 0x0b];
+        let options = DecodeOptions {
+            preserve_map_keys: true,
+            ..Default::default()
+        };
+        let (value, _, report) = parse_msgpack_value_with_report(&msgpack, 0, &options).unwrap();
+        assert_eq!(value, serde_json::json!({"$map": [[1, 10], ["1", 11]]}));
+        assert!(report.duplicate_keys.is_empty());
+        let (value, _, report) =
+            parse_msgpack_value_with_report(&msgpack, 0, &DecodeOptions::default()).unwrap();
+        assert_eq!(value, serde_json::json!({"1": 11}));
+        assert_eq!(
+            report.duplicate_keys,
+            vec![DuplicateKey {
+                range: (3, 5),
+                key: "\"1\"".to_string(),
+                kept: false
+            }]
+        );
+
+        // A key that really repeats is reported in pairs too, as kept
+        let msgpack = [0x82, 0x01, 0x0a, 0x01, 0x0b];
+        let (_, _, report) = parse_msgpack_value_with_report(&msgpack, 0, &options).unwrap();
+        assert_eq!(
+            report.duplicate_keys,
+            vec![DuplicateKey {
+                range: (3, 4),
+                key: "1".to_string(),
+                kept: true
+            }]
+        );
+    }
+
+    #[test]
+    fn test_create_position_mappings_stream() {
+        // 1, "a", [true] back to back
+        let msgpack = [0x01, 0xa1, 0x61, 0x91, 0xc3];
+        let spans = |json: &str, stream: StreamFormat| {
+            let options = DecodeOptions {
+                stream,
+                ..DecodeOptions::default()
+            };
+            create_position_mappings(&msgpack, json, &options)
+                .iter()
+                .filter(|m| m.mapping_type == MappingType::Value)
+                .map(|m| (json[m.json_start..m.json_end].to_string(), m.hex_start))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            ("1".to_string(), 0),
+            ("\"a\"".to_string(), 1),
+            ("true".to_string(), 4),
+        ];
+
+        let array_json = "[\n  1,\n  \"a\",\n  [\n    true\n  ]\n]";
+        assert_eq!(spans(array_json, StreamFormat::JsonArray), expected);
+        assert_eq!(spans("1\n\"a\"\n[true]", StreamFormat::Ndjson), expected);
+    }
+
+    #[test]
+    fn test_create_position_mappings_invalid_utf8() {
+        // ["a\xff", "é"]
+        let msgpack = [0x92, 0xa2, 0x61, 0xff, 0xa2, 0xc3, 0xa9];
+        let json = "[\n  {\n    \"$rawstr\": \"Yf8=\"\n  },\n  \"é\"\n]";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        let chars: Vec<char> = json.chars().collect();
+        let spans: Vec<String> = mappings
+            .iter()
+            .map(|m| chars[m.json_start..m.json_end].iter().collect())
+            .collect();
+        assert_eq!(spans, vec!["{\n    \"$rawstr\": \"Yf8=\"\n  }", "\"é\""]);
+        assert_eq!((mappings[1].hex_start, mappings[1].hex_end), (4, 7));
+    }
+
+    #[test]
+    fn test_create_position_mappings_special_floats() {
+        // [NaN, -0.0, 1] as float64 values
+        let msgpack = [
+            &[0x93, 0xcb][..],
+            &f64::NAN.to_be_bytes(),
+            &[0xcb],
+            &(-0.0f64).to_be_bytes(),
+            &[0x01],
+        ]
+        .concat();
+        let json = "[\n  {\n    \"$float64\": \"NaN\"\n  },\n  -0.0,\n  1\n]";
+
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
+
+        let spans: Vec<_> = mappings
+            .iter()
+            .map(|m| (&json[m.json_start..m.json_end], m.hex_start, m.hex_end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("{\n    \"$float64\": \"NaN\"\n  }", 1, 10),
+                ("-0.0", 10, 19),
+                ("1", 19, 20)
+            ]
+        );
+    }
+
     #[test]
     fn test_find_hex_range_for_json_selection() {
         let msgpack = vec![0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b];
         let json = "{\n  \"hello\": 123\n}";
-        let mappings = create_position_mappings(&msgpack, json);
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
 
         // Select "hello" in JSON (including quotes)
         let hello_start = json.find("\"hello\"").unwrap();
@@ -1060,7 +2665,7 @@ mod tests {
     fn test_find_hex_range_no_selection() {
         let msgpack = vec![0x81, 0xa5, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x7b];
         let json = "{\n  \"hello\": 123\n}";
-        let mappings = create_position_mappings(&msgpack, json);
+        let mappings = create_position_mappings(&msgpack, json, &DecodeOptions::default());
 
         // Select just the opening brace
         let range = find_hex_range_for_json_selection(&mappings, 0, 1);
@@ -1070,19 +2675,39 @@ mod tests {
     #[test]
     fn test_byte_range_to_hex_char_range() {
         // Byte 0 in "81 A5 68" is "81" at chars 0-1
-        let (start, end) = byte_range_to_hex_char_range(0, 1);
-        assert_eq!(start, 0);
-        assert_eq!(end, 2);
+        let ranges = byte_range_to_hex_char_range(0, 1, HexLayout::Inline);
+        assert_eq!(ranges, vec![(0, 2)]);
 
         // Bytes 0-2 in "81 A5 68" is "81 A5" at chars 0-4
-        let (start, end) = byte_range_to_hex_char_range(0, 2);
-        assert_eq!(start, 0);
-        assert_eq!(end, 5);
+        let ranges = byte_range_to_hex_char_range(0, 2, HexLayout::Inline);
+        assert_eq!(ranges, vec![(0, 5)]);
 
         // Bytes 1-3 in "81 A5 68 65" is "A5 68" at chars 3-7
-        let (start, end) = byte_range_to_hex_char_range(1, 3);
-        assert_eq!(start, 3);
-        assert_eq!(end, 8);
+        let ranges = byte_range_to_hex_char_range(1, 3, HexLayout::Inline);
+        assert_eq!(ranges, vec![(3, 8)]);
+
+        assert!(byte_range_to_hex_char_range(2, 2, HexLayout::Inline).is_empty());
+    }
+
+    #[test]
+    fn test_byte_range_to_hex_char_range_dump() {
+        let bytes: Vec<u8> = (0x40..0x54).collect();
+        let dump = format_hex_dump(&bytes);
+        assert_eq!(
+            dump,
+            "00000000  40 41 42 43 44 45 46 47  48 49 4A 4B 4C 4D 4E 4F  |@ABCDEFGHIJKLMNO|\n\
+             00000010  50 51 52 53                                       |PQRS|"
+        );
+
+        // Bytes 7-9 straddle the gap between the two groups of eight
+        let ranges = byte_range_to_hex_char_range(7, 9, HexLayout::Dump);
+        let cells: Vec<&str> = ranges.iter().map(|&(s, e)| &dump[s..e]).collect();
+        assert_eq!(cells, vec!["47  48", "GH"]);
+
+        // Bytes 15-18 span two rows
+        let ranges = byte_range_to_hex_char_range(15, 18, HexLayout::Dump);
+        let cells: Vec<&str> = ranges.iter().map(|&(s, e)| &dump[s..e]).collect();
+        assert_eq!(cells, vec!["4F", "O", "50 51", "PQ"]);
     }
 
     #[test]
@@ -1090,15 +2715,15 @@ mod tests {
         let data = vec![0x01, 0x02, 0x03];
 
         // Should succeed
-        let result: Result<[u8; 2], String> = safe_array(&data, 0);
-        assert!(result.is_ok());
+        let result: Result<[u8; 2], DecodeError> = safe_array(&data, 0, 1);
+        assert_eq!(result, Ok([0x02, 0x03]));
 
-        // Should fail - not enough bytes
-        let result: Result<[u8; 4], String> = safe_array(&data, 0);
-        assert!(result.is_err());
+        // Should fail - not enough bytes, reported at the value's offset
+        let result: Result<[u8; 4], DecodeError> = safe_array(&data, 0, 1);
+        assert_eq!(result, Err(DecodeError::truncated(&data, 0, 5)));
 
         // Should fail - start position too far
-        let result: Result<[u8; 2], String> = safe_array(&data, 10);
+        let result: Result<[u8; 2], DecodeError> = safe_array(&data, 10, 0);
         assert!(result.is_err());
     }
 
@@ -1107,19 +2732,115 @@ mod tests {
         let data = vec![0x01, 0x02];
 
         // Should succeed
-        assert_eq!(safe_byte(&data, 0).unwrap(), 0x01);
-        assert_eq!(safe_byte(&data, 1).unwrap(), 0x02);
+        assert_eq!(safe_byte(&data, 0, 0).unwrap(), 0x01);
+        assert_eq!(safe_byte(&data, 0, 1).unwrap(), 0x02);
 
         // Should fail
-        assert!(safe_byte(&data, 2).is_err());
-        assert!(safe_byte(&data, 100).is_err());
+        assert!(safe_byte(&data, 1, 1).is_err());
+        assert!(safe_byte(&data, 100, 0).is_err());
     }
 
     #[test]
     fn test_truncated_data_handling() {
         // Truncated float32 data (needs 5 bytes, only 2 provided)
         let truncated_data = vec![0xca, 0x40]; // float32 marker + 1 byte
-        let result = parse_msgpack_value(&truncated_data, 0);
+        let result = parse_msgpack_value(&truncated_data, 0, &DecodeOptions::default());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_huge_length_prefixes() {
+        // str 32, bin 32 and ext 32 claiming u32::MAX bytes
+        for data in [
+            &[0xdb, 0xff, 0xff, 0xff, 0xff][..],
+            &[0xc6, 0xff, 0xff, 0xff, 0xff],
+            &[0xc9, 0xff, 0xff, 0xff, 0xff, 0x01],
+        ] {
+            let err = parse_msgpack_value(data, 0, &DecodeOptions::default()).unwrap_err();
+            assert!(matches!(err.kind, DecodeErrorKind::Truncated { .. }));
+            assert!(create_position_mappings(data, "\"\"", &DecodeOptions::default()).is_empty());
+        }
+        // Where usize is 32 bits, offset plus header plus length wraps; it must not pass
+        let data = [0xdb, 0xff, 0xff, 0xff, 0xff];
+        assert!(payload_end(&data, usize::MAX - 5, 5, u32::MAX as usize).is_err());
+        assert!(payload_end(&data, 0, usize::MAX, u32::MAX as usize).is_err());
+        assert_eq!(payload_end(&data, 1, 1, 3), Ok(5));
+    }
+
+    #[test]
+    fn test_hostile_input_is_rejected() {
+        // A million nested fixarrays would overflow the stack without a depth limit
+        let mut nested = vec![0x91; 1_000_000];
+        nested.push(0x01);
+        let err = parse_msgpack_value(&nested, 0, &DecodeOptions::default()).unwrap_err();
+        assert_eq!(
+            err.kind,
+            DecodeErrorKind::LimitExceeded {
+                limit: LimitKind::Depth,
+                max: 64,
+                actual: 65
+            }
+        );
+        assert_eq!(err.offset, 64);
+        assert!(
+            create_position_mappings(&nested[..2000], "", &DecodeOptions::default()).is_empty()
+        );
+
+        // array 32 claiming 4 billion elements
+        let huge = [0xdd, 0xff, 0xff, 0xff, 0xff, 0xc0];
+        let err = parse_msgpack_value(&huge, 0, &DecodeOptions::default()).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DecodeErrorKind::LimitExceeded {
+                limit: LimitKind::Elements,
+                ..
+            }
+        ));
+        // Without an element limit the count is still checked against the data
+        let options = DecodeOptions {
+            limits: Limits {
+                max_elements: usize::MAX,
+                ..Limits::default()
+            },
+            ..DecodeOptions::default()
+        };
+        let err = parse_msgpack_value(&huge, 0, &options).unwrap_err();
+        assert!(matches!(err.kind, DecodeErrorKind::Truncated { .. }));
+        // map 32 entries take at least two bytes each
+        let err =
+            parse_msgpack_value(&[0xdf, 0, 0, 0, 2, 0xc0, 0xc0, 0xc0], 0, &options).unwrap_err();
+        assert_eq!(
+            err.kind,
+            DecodeErrorKind::Truncated {
+                expected: 9,
+                available: 8
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_error_location() {
+        // {"items": [1, "ab<truncated>"]}: str 8 at offset 9 claims 5 bytes, 2 present
+        let data = [
+            0x81, 0xa5, b'i', b't', b'e', b'm', b's', 0x92, 0x01, 0xd9, 0x05, b'a', b'b',
+        ];
+        let err = parse_msgpack_value(&data, 0, &DecodeOptions::default()).unwrap_err();
+        assert_eq!(err.offset, 9);
+        assert_eq!(err.format_byte, Some(0xd9));
+        assert_eq!(err.path, "$.items[1]");
+        assert_eq!(
+            err.kind,
+            DecodeErrorKind::Truncated {
+                expected: 7,
+                available: 4
+            }
+        );
+        assert_eq!(err.byte_range(), Some((9, 13)));
+
+        // Never-used format byte inside an array
+        let err =
+            parse_msgpack_value(&[0x92, 0x01, 0xc1], 0, &DecodeOptions::default()).unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidFormat);
+        assert_eq!((err.offset, err.path.as_str()), (2, "$[1]"));
+    }
 }
@@ -0,0 +1,611 @@
+use base64::Engine;
+use rmp::Marker;
+use serde_json::{Map, Value};
+
+use crate::error::key_path_segment;
+use crate::format;
+
+/// Tag for msgpack extension values: `{"$ext": {"type": <i8>, "data": "<base64>"}}`
+pub const EXT_TAG: &str = "$ext";
+
+/// Tag for binary values (bin 8/16/32): `{"$bin": "<base64>"}`
+pub const BIN_TAG: &str = "$bin";
+
+/// Tag for str values that are not valid UTF-8: `{"$rawstr": "<base64>"}`
+pub const RAW_STR_TAG: &str = "$rawstr";
+
+/// Tag for maps with non-string keys: `{"$map": [[<key>, <value>], ...]}`
+pub const MAP_TAG: &str = "$map";
+
+/// Tag for timestamp extension values (type -1): `{"$timestamp": "<RFC 3339>"}`
+pub const TIMESTAMP_TAG: &str = "$timestamp";
+
+/// Marker left where the data ran out in a partial decode:
+/// `{"$truncated": {"offset": <n>, "expected": <bytes>, "available": <bytes>}}`
+pub const TRUNCATED_TAG: &str = "$truncated";
+
+/// Extension type reserved by the msgpack spec for timestamps
+pub const TIMESTAMP_EXT_TYPE: i8 = -1;
+
+/// Whether a single-key object with this key is read back as a tag or a wire-format
+/// annotation rather than as a map
+pub fn is_tag_name(key: &str) -> bool {
+    [
+        EXT_TAG,
+        BIN_TAG,
+        RAW_STR_TAG,
+        MAP_TAG,
+        TIMESTAMP_TAG,
+        TRUNCATED_TAG,
+    ]
+    .contains(&key)
+        || annotation_format(key).is_some()
+}
+
+/// Wrap a payload in a single-key tagged object such as `{"$ext": ...}`
+fn tag(name: &str, payload: Value) -> Value {
+    let mut obj = Map::new();
+    obj.insert(name.to_string(), payload);
+    Value::Object(obj)
+}
+
+/// Return the tag name and payload if the value is a single-key object whose key starts with `$`
+pub fn as_tagged(value: &Value) -> Option<(&str, &Value)> {
+    match value {
+        Value::Object(obj) if obj.len() == 1 => {
+            let (key, payload) = obj.iter().next()?;
+            key.starts_with('$').then_some((key.as_str(), payload))
+        }
+        _ => None,
+    }
+}
+
+/// Wrap a value in a wire-format annotation such as `{"$uint16": 1}` or `{"$str8": "a"}`
+pub fn annotate_format(marker: Marker, value: Value) -> Value {
+    tag(&format!("${}", format::format_name(marker)), value)
+}
+
+/// The format requested by a wire-format annotation tag, if the tag is one
+pub fn annotation_format(tag_name: &str) -> Option<Marker> {
+    tag_name.strip_prefix('$').and_then(format::sized_format)
+}
+
+/// Textual form of a non-finite float32 (`marker` F32) or float64 given its raw bits:
+/// `Infinity`, `-Infinity`, `NaN` for the default quiet NaN, and `NaN:0x<bits>` otherwise.
+pub fn non_finite_to_json(marker: Marker, bits: u64) -> Value {
+    let (value, nan_bits, hex_width) = match marker {
+        Marker::F32 => (
+            f32::from_bits(bits as u32) as f64,
+            f32::NAN.to_bits() as u64,
+            8,
+        ),
+        _ => (f64::from_bits(bits), f64::NAN.to_bits(), 16),
+    };
+    let text = if value == f64::INFINITY {
+        "Infinity".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Infinity".to_string()
+    } else if bits == nan_bits {
+        "NaN".to_string()
+    } else {
+        format!("NaN:0x{:0width$x}", bits, width = hex_width)
+    };
+    Value::String(text)
+}
+
+/// Raw float32 (`marker` F32) or float64 bits for the textual form of a non-finite float
+pub fn parse_non_finite(marker: Marker, text: &str) -> Result<u64, String> {
+    let is_f32 = marker == Marker::F32;
+    let bits = match text {
+        "Infinity" if is_f32 => f32::INFINITY.to_bits() as u64,
+        "Infinity" => f64::INFINITY.to_bits(),
+        "-Infinity" if is_f32 => f32::NEG_INFINITY.to_bits() as u64,
+        "-Infinity" => f64::NEG_INFINITY.to_bits(),
+        "NaN" if is_f32 => f32::NAN.to_bits() as u64,
+        "NaN" => f64::NAN.to_bits(),
+        _ => {
+            let bits = text
+                .strip_prefix("NaN:0x")
+                .and_then(|hex| u64::from_str_radix(hex, 16).ok());
+            let is_nan = match bits {
+                Some(b) if is_f32 => b <= u32::MAX as u64 && f32::from_bits(b as u32).is_nan(),
+                Some(b) => f64::from_bits(b).is_nan(),
+                None => false,
+            };
+            if !is_nan {
+                return Err(format!(
+                    "Invalid float value {:?}: expected a number, \"NaN\", \"Infinity\" or \"-Infinity\"",
+                    text
+                ));
+            }
+            bits.unwrap_or_default()
+        }
+    };
+    Ok(bits)
+}
+
+/// Build the marker for a value at `offset` that needed `expected` bytes when only
+/// `available` were left
+pub fn truncated_marker(offset: usize, expected: usize, available: usize) -> Value {
+    tag(
+        TRUNCATED_TAG,
+        serde_json::json!({"offset": offset, "expected": expected, "available": available}),
+    )
+}
+
+/// Build the tagged JSON representation of a binary value
+pub fn bin_to_json(data: &[u8]) -> Value {
+    tag(
+        BIN_TAG,
+        Value::String(base64::engine::general_purpose::STANDARD.encode(data)),
+    )
+}
+
+/// Extract the raw bytes from the payload of a `$bin` tag
+pub fn json_to_bin(payload: &Value) -> Result<Vec<u8>, String> {
+    let data = payload
+        .as_str()
+        .ok_or_else(|| "$bin must be a base64 string".to_string())?;
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid base64 in $bin: {}", e))
+}
+
+/// JSON for a str payload: a plain string when it is valid UTF-8, otherwise a
+/// `$rawstr` tag holding the raw bytes so they re-encode unchanged
+pub fn str_to_json(data: &[u8]) -> Value {
+    match std::str::from_utf8(data) {
+        Ok(s) => Value::String(s.to_string()),
+        Err(_) => tag(
+            RAW_STR_TAG,
+            Value::String(base64::engine::general_purpose::STANDARD.encode(data)),
+        ),
+    }
+}
+
+/// Extract the raw bytes from the payload of a `$rawstr` tag
+pub fn json_to_raw_str(payload: &Value) -> Result<Vec<u8>, String> {
+    let data = payload
+        .as_str()
+        .ok_or_else(|| "$rawstr must be a base64 string".to_string())?;
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid base64 in $rawstr: {}", e))
+}
+
+/// JSON paths (e.g. `$.users[0].name`) and payloads of every value carrying the given tag
+pub fn find_tagged<'a>(value: &'a Value, tag_name: &str) -> Vec<(String, &'a Value)> {
+    let mut found = Vec::new();
+    collect_tagged(value, tag_name, "$".to_string(), &mut found);
+    found
+}
+
+fn collect_tagged<'a>(
+    value: &'a Value,
+    tag_name: &str,
+    path: String,
+    found: &mut Vec<(String, &'a Value)>,
+) {
+    if let Some((name, payload)) = as_tagged(value) {
+        if name == tag_name {
+            found.push((path, payload));
+            return;
+        }
+    }
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_tagged(item, tag_name, format!("{}[{}]", path, i), found);
+            }
+        }
+        Value::Object(obj) => {
+            for (key, item) in obj {
+                let child = format!("{}{}", path, key_path_segment(key));
+                collect_tagged(item, tag_name, child, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Build the tagged JSON representation of a map as an array of `[key, value]` pairs
+pub fn map_pairs_to_json(entries: Vec<(Value, Value)>) -> Value {
+    let pairs = entries
+        .into_iter()
+        .map(|(key, value)| Value::Array(vec![key, value]))
+        .collect();
+    tag(MAP_TAG, Value::Array(pairs))
+}
+
+/// Extract the key-value pairs from the payload of a `$map` tag
+pub fn json_to_map_pairs(payload: &Value) -> Result<Vec<(&Value, &Value)>, String> {
+    let invalid = || "$map must be an array of [key, value] pairs".to_string();
+    payload
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|pair| match pair.as_array().map(Vec::as_slice) {
+            Some([key, value]) => Ok((key, value)),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Build the tagged JSON representation of an extension value
+pub fn ext_to_json(ext_type: i8, data: &[u8]) -> Value {
+    let mut payload = Map::new();
+    payload.insert("type".to_string(), Value::Number(ext_type.into()));
+    payload.insert(
+        "data".to_string(),
+        Value::String(base64::engine::general_purpose::STANDARD.encode(data)),
+    );
+    tag(EXT_TAG, Value::Object(payload))
+}
+
+/// Extract the extension type and raw data from the payload of an `$ext` tag
+pub fn json_to_ext(payload: &Value) -> Result<(i8, Vec<u8>), String> {
+    let ext_type = payload
+        .get("type")
+        .and_then(Value::as_i64)
+        .and_then(|t| i8::try_from(t).ok())
+        .ok_or_else(|| "$ext type must be an integer between -128 and 127".to_string())?;
+    let data = payload
+        .get("data")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "$ext data must be a base64 string".to_string())?;
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid base64 in $ext data: {}", e))?;
+    Ok((ext_type, data))
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Format seconds and nanoseconds since the Unix epoch as an RFC 3339 UTC string.
+/// Returns `None` for instants outside the four-digit years RFC 3339 can express.
+pub fn format_rfc3339(seconds: i64, nanos: u32) -> Option<String> {
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+    let secs_of_day = seconds.rem_euclid(86_400);
+    let fraction = match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    };
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        fraction
+    ))
+}
+
+/// Parse an RFC 3339 date-time into seconds and nanoseconds since the Unix epoch
+pub fn parse_rfc3339(s: &str) -> Result<(i64, u32), String> {
+    let invalid = || format!("Invalid RFC 3339 timestamp: {}", s);
+    let digits = |range: std::ops::Range<usize>| -> Result<u32, String> {
+        let part = s.get(range).ok_or_else(invalid)?;
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        part.parse().map_err(|_| invalid())
+    };
+    let bytes = s.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if bytes.len() < 20
+        || separators
+            .iter()
+            .any(|&(i, c)| !bytes[i].eq_ignore_ascii_case(&c))
+    {
+        return Err(invalid());
+    }
+
+    let year = digits(0..4)? as i64;
+    let month = digits(5..7)?;
+    let day = digits(8..10)?;
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+    if !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+
+    let mut pos = 19;
+    let mut nanos = 0;
+    if bytes[pos] == b'.' {
+        let frac_len = bytes[pos + 1..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if frac_len == 0 || frac_len > 9 {
+            return Err(invalid());
+        }
+        nanos = digits(pos + 1..pos + 1 + frac_len)? * 10u32.pow(9 - frac_len as u32);
+        pos += 1 + frac_len;
+    }
+
+    let offset_seconds = match &s[pos..] {
+        "Z" | "z" => 0,
+        offset if offset.len() == 6 && offset.as_bytes()[3] == b':' => {
+            let sign = match offset.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(invalid()),
+            };
+            let (offset_hour, offset_minute) =
+                (digits(pos + 1..pos + 3)?, digits(pos + 4..pos + 6)?);
+            if offset_hour > 23 || offset_minute > 59 {
+                return Err(invalid());
+            }
+            sign * (offset_hour as i64 * 3600 + offset_minute as i64 * 60)
+        }
+        _ => return Err(invalid()),
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86_400
+        + hour as i64 * 3600
+        + minute as i64 * 60
+        + second as i64
+        - offset_seconds;
+    Ok((seconds, nanos))
+}
+
+/// Decode the data of a timestamp extension (timestamp 32/64/96).
+/// Returns `None` if the data is not a valid timestamp.
+pub fn decode_timestamp(data: &[u8]) -> Option<(i64, u32)> {
+    match data.len() {
+        4 => Some((u32::from_be_bytes(data.try_into().ok()?) as i64, 0)),
+        8 => {
+            let value = u64::from_be_bytes(data.try_into().ok()?);
+            Some(((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32))
+        }
+        12 => {
+            let nanos = u32::from_be_bytes(data[..4].try_into().ok()?);
+            let seconds = i64::from_be_bytes(data[4..].try_into().ok()?);
+            Some((seconds, nanos))
+        }
+        _ => None,
+    }
+}
+
+/// Encode seconds and nanoseconds as timestamp extension data,
+/// choosing the smallest of timestamp 32, 64 and 96.
+pub fn encode_timestamp(seconds: i64, nanos: u32) -> Vec<u8> {
+    if seconds >> 34 == 0 {
+        if nanos == 0 && seconds <= u32::MAX as i64 {
+            return (seconds as u32).to_be_bytes().to_vec();
+        }
+        return ((nanos as u64) << 34 | seconds as u64)
+            .to_be_bytes()
+            .to_vec();
+    }
+    let mut data = nanos.to_be_bytes().to_vec();
+    data.extend_from_slice(&seconds.to_be_bytes());
+    data
+}
+
+/// Build the tagged JSON representation of a timestamp extension value.
+/// Falls back to the generic `$ext` form when the data cannot be shown as RFC 3339,
+/// or, if `require_smallest` is set, when re-encoding it would pick a smaller format.
+pub fn timestamp_to_json(data: &[u8], require_smallest: bool) -> Value {
+    let formatted = decode_timestamp(data)
+        .filter(|&(seconds, nanos)| !require_smallest || encode_timestamp(seconds, nanos) == data)
+        .and_then(|(seconds, nanos)| format_rfc3339(seconds, nanos));
+    match formatted {
+        Some(formatted) => tag(TIMESTAMP_TAG, Value::String(formatted)),
+        None => ext_to_json(TIMESTAMP_EXT_TYPE, data),
+    }
+}
+
+/// Convert the payload of a `$timestamp` tag into timestamp extension data
+pub fn json_to_timestamp(payload: &Value) -> Result<Vec<u8>, String> {
+    let s = payload
+        .as_str()
+        .ok_or_else(|| "$timestamp must be an RFC 3339 string".to_string())?;
+    let (seconds, nanos) = parse_rfc3339(s)?;
+    Ok(encode_timestamp(seconds, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_ext_roundtrip() {
+        let value = ext_to_json(5, &[0x01, 0x02, 0x03]);
+        assert_eq!(value.to_string(), r#"{"$ext":{"type":5,"data":"AQID"}}"#);
+        let (tag_name, payload) = as_tagged(&value).unwrap();
+        assert_eq!(tag_name, EXT_TAG);
+        assert_eq!(json_to_ext(payload).unwrap(), (5, vec![0x01, 0x02, 0x03]));
+    }
+
+    #[test]
+    fn test_bin_roundtrip() {
+        let value = bin_to_json(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(value.to_string(), r#"{"$bin":"3q2+7w=="}"#);
+        let (tag_name, payload) = as_tagged(&value).unwrap();
+        assert_eq!(tag_name, BIN_TAG);
+        assert_eq!(json_to_bin(payload).unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(json_to_bin(&Value::Bool(true)).is_err());
+    }
+
+    #[test]
+    fn test_map_pairs_roundtrip() {
+        let value = map_pairs_to_json(vec![(Value::from(1), Value::from("a"))]);
+        assert_eq!(value.to_string(), r#"{"$map":[[1,"a"]]}"#);
+        let (tag_name, payload) = as_tagged(&value).unwrap();
+        assert_eq!(tag_name, MAP_TAG);
+        let pairs = json_to_map_pairs(payload).unwrap();
+        assert_eq!(pairs, vec![(&Value::from(1), &Value::from("a"))]);
+
+        let not_pairs: Value = serde_json::from_str("[[1, 2, 3]]").unwrap();
+        assert!(json_to_map_pairs(&not_pairs).is_err());
+    }
+
+    #[test]
+    fn test_non_finite_roundtrip() {
+        for (marker, bits) in [
+            (Marker::F64, f64::INFINITY.to_bits()),
+            (Marker::F64, f64::NEG_INFINITY.to_bits()),
+            (Marker::F64, f64::NAN.to_bits()),
+            (Marker::F64, 0xfff0_0000_0000_0001),
+            (Marker::F32, f32::NAN.to_bits() as u64),
+            (Marker::F32, 0xffc0_0000),
+        ] {
+            let text = non_finite_to_json(marker, bits);
+            assert_eq!(parse_non_finite(marker, text.as_str().unwrap()), Ok(bits));
+        }
+        assert_eq!(
+            non_finite_to_json(Marker::F32, 0xffc0_0000),
+            Value::from("NaN:0xffc00000")
+        );
+        assert!(parse_non_finite(Marker::F64, "nan").is_err());
+        assert!(parse_non_finite(Marker::F64, "NaN:0x3ff0000000000000").is_err());
+        assert!(parse_non_finite(Marker::F32, "NaN:0x7ff8000000000000").is_err());
+    }
+
+    #[test]
+    fn test_str_to_json() {
+        assert_eq!(str_to_json("héllo".as_bytes()), Value::from("héllo"));
+        let raw = str_to_json(&[0x61, 0xff, 0x62]);
+        assert_eq!(raw, json!({"$rawstr": "Yf9i"}));
+        assert_eq!(
+            json_to_raw_str(&raw[RAW_STR_TAG]),
+            Ok(vec![0x61, 0xff, 0x62])
+        );
+        assert!(json_to_raw_str(&json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_find_tagged() {
+        let value = json!({
+            "name": {"$rawstr": "/w=="},
+            "items": [1, {"$rawstr": "/w=="}],
+            "odd key": {"$rawstr": "/w=="},
+            "bin": {"$bin": "/w=="}
+        });
+        let found = find_tagged(&value, RAW_STR_TAG);
+        let paths: Vec<&str> = found.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["$.name", "$.items[1]", "$[\"odd key\"]"]);
+        assert_eq!(found[0].1, "/w==");
+    }
+
+    #[test]
+    fn test_as_tagged_requires_single_dollar_key() {
+        let plain: Value = serde_json::from_str(r#"{"ext": 1}"#).unwrap();
+        assert!(as_tagged(&plain).is_none());
+        let two_keys: Value = serde_json::from_str(r#"{"$ext": 1, "b": 2}"#).unwrap();
+        assert!(as_tagged(&two_keys).is_none());
+    }
+
+    #[test]
+    fn test_json_to_ext_rejects_out_of_range_type() {
+        let payload: Value = serde_json::from_str(r#"{"type": 300, "data": ""}"#).unwrap();
+        assert!(json_to_ext(&payload).is_err());
+    }
+
+    #[test]
+    fn test_rfc3339_roundtrip() {
+        for (seconds, nanos) in [
+            (0, 0),
+            (1_700_000_000, 123_000_000),
+            (951_782_400, 1_000), // 2000-02-29
+            (-1, 999_999_999),
+            (253_402_300_799, 0), // 9999-12-31T23:59:59Z
+        ] {
+            let formatted = format_rfc3339(seconds, nanos).unwrap();
+            assert_eq!(parse_rfc3339(&formatted).unwrap(), (seconds, nanos));
+        }
+        assert_eq!(format_rfc3339(0, 0).unwrap(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_rfc3339(1_700_000_000, 5).unwrap(),
+            "2023-11-14T22:13:20.000000005Z"
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3339_offsets_and_errors() {
+        assert_eq!(parse_rfc3339("1970-01-01T01:00:00+01:00").unwrap(), (0, 0));
+        assert_eq!(
+            parse_rfc3339("1970-01-01t00:00:00.5z").unwrap(),
+            (0, 500_000_000)
+        );
+        assert!(parse_rfc3339("2023-02-29T00:00:00Z").is_err());
+        assert!(parse_rfc3339("2023-01-01 00:00:00Z").is_err());
+        assert!(parse_rfc3339("2023-01-01T00:00:00").is_err());
+        assert!(parse_rfc3339("2023-01-01T00:00:00.1234567890Z").is_err());
+    }
+
+    #[test]
+    fn test_encode_timestamp_picks_smallest_form() {
+        assert_eq!(encode_timestamp(1, 0).len(), 4);
+        assert_eq!(encode_timestamp(1, 1).len(), 8);
+        assert_eq!(encode_timestamp(1 << 33, 0).len(), 8);
+        assert_eq!(encode_timestamp(-1, 0).len(), 12);
+        assert_eq!(encode_timestamp(1 << 34, 0).len(), 12);
+        for (seconds, nanos) in [(1, 0), (1, 1), (-1, 5), (1 << 34, 7)] {
+            let data = encode_timestamp(seconds, nanos);
+            assert_eq!(decode_timestamp(&data), Some((seconds, nanos)));
+        }
+    }
+
+    #[test]
+    fn test_timestamp_to_json_falls_back_to_ext() {
+        // Nanoseconds out of range are not a valid timestamp
+        let data = encode_timestamp(-1, 1_500_000_000);
+        let value = timestamp_to_json(&data, false);
+        assert_eq!(value[EXT_TAG]["type"], -1);
+
+        // timestamp 64 holding a value that fits in timestamp 32
+        let data = 1u64.to_be_bytes();
+        assert!(timestamp_to_json(&data, false)[TIMESTAMP_TAG].is_string());
+        assert_eq!(timestamp_to_json(&data, true)[EXT_TAG]["type"], -1);
+    }
+}